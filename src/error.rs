@@ -1,3 +1,6 @@
+use crate::machine::record::Divergence;
+use crate::machine::value::MachineValue;
+use crate::op::Op;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
@@ -11,6 +14,26 @@ pub enum MachineError {
     ValueExpected,
     RegisterExpected,
     CallStackEmpty,
+    HandlerStackEmpty,
+    DivisionByZero,
+    Overflow,
+    Uncaught(MachineValue),
+    SnapshotInvalid,
+    SnapshotMismatch,
+    RecordingInvalid,
+    RecordingMismatch,
+    Divergence(Box<Divergence>),
+    RewindOutOfRange(u64),
+    Fault(Box<Fault>),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Fault {
+    pub error: MachineError,
+    pub pc: usize,
+    pub op: Option<Op>,
+    pub depth: usize,
+    pub backtrace: Vec<usize>,
 }
 
 impl Display for MachineError {
@@ -22,8 +45,301 @@ impl Display for MachineError {
             MachineError::ValueExpected => write!(f, "value expected"),
             MachineError::RegisterExpected => write!(f, "register expected"),
             MachineError::CallStackEmpty => write!(f, "call stack empty"),
+            MachineError::HandlerStackEmpty => write!(f, "handler stack empty"),
+            MachineError::DivisionByZero => write!(f, "division by zero"),
+            MachineError::Overflow => write!(f, "arithmetic overflow"),
+            MachineError::Uncaught(value) => write!(f, "uncaught exception: {}", value),
+            MachineError::SnapshotInvalid => write!(f, "snapshot invalid"),
+            MachineError::SnapshotMismatch => write!(f, "snapshot does not match program"),
+            MachineError::RecordingInvalid => write!(f, "recording invalid"),
+            MachineError::RecordingMismatch => write!(f, "recording does not match program"),
+            MachineError::Divergence(divergence) => write!(f, "{}", divergence),
+            MachineError::RewindOutOfRange(step) => {
+                write!(f, "step {} is no longer in the journal", step)
+            }
+            MachineError::Fault(fault) => write!(f, "{}", fault),
+        }
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "{}", self.error)?;
+        match self.op {
+            Some(op) => write!(f, "    at #{}: {}", self.pc, op)?,
+            None => write!(f, "    at #{}: <no instruction>", self.pc)?,
+        }
+        write!(f, " (stack depth {})", self.depth)?;
+        for address in &self.backtrace {
+            write!(f, "\n    called from #{}", address.saturating_sub(1))?;
+        }
+        Ok(())
+    }
+}
+
+impl MachineError {
+    pub fn code(&self) -> u32 {
+        match self {
+            MachineError::StackEmpty => 1,
+            MachineError::InstructionExpected => 2,
+            MachineError::InstructionOverflow => 3,
+            MachineError::ValueExpected => 4,
+            MachineError::RegisterExpected => 5,
+            MachineError::CallStackEmpty => 6,
+            MachineError::HandlerStackEmpty => 7,
+            MachineError::DivisionByZero => 8,
+            MachineError::Uncaught(_) => 9,
+            MachineError::SnapshotInvalid => 10,
+            MachineError::SnapshotMismatch => 11,
+            MachineError::RecordingInvalid => 12,
+            MachineError::RecordingMismatch => 13,
+            MachineError::Divergence(_) => 14,
+            MachineError::Overflow => 15,
+            MachineError::RewindOutOfRange(_) => 16,
+            MachineError::Fault(fault) => fault.error.code(),
+        }
+    }
+
+    pub fn root(&self) -> &MachineError {
+        match self {
+            MachineError::Fault(fault) => fault.error.root(),
+            error => error,
         }
     }
 }
 
 impl Error for MachineError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ParseArgError(pub String);
+
+impl Display for ParseArgError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "invalid argument `{}`", self.0)
+    }
+}
+
+impl Error for ParseArgError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FormatError {
+    Truncated,
+    Magic,
+    Version(u16),
+    Flags(u16),
+    Checksum { expected: u32, actual: u32 },
+    Section(u16),
+    SectionInvalid(u16),
+    MissingSection(u16),
+    Decode(DecodeError),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            FormatError::Truncated => write!(f, "program truncated"),
+            FormatError::Magic => write!(f, "not a tinyvm program (missing magic number)"),
+            FormatError::Version(version) => {
+                write!(f, "unsupported program format version {}", version)
+            }
+            FormatError::Flags(flags) => write!(f, "unsupported program flags {:#06x}", flags),
+            FormatError::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, found {:08x}",
+                expected, actual
+            ),
+            FormatError::Section(tag) => write!(f, "section {} out of bounds", tag),
+            FormatError::SectionInvalid(tag) => write!(f, "section {} is malformed", tag),
+            FormatError::MissingSection(tag) => write!(f, "missing section {}", tag),
+            FormatError::Decode(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl From<DecodeError> for FormatError {
+    fn from(error: DecodeError) -> Self {
+        FormatError::Decode(error)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DecodeReason {
+    UnknownOpCode(u8),
+    UnknownArgTag(u8),
+    InvalidValue,
+    Truncated,
+    TrailingBytes(usize),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub index: usize,
+    pub reason: DecodeReason,
+}
+
+impl DecodeError {
+    pub const fn new(offset: usize, reason: DecodeReason) -> DecodeError {
+        Self {
+            offset,
+            index: 0,
+            reason,
+        }
+    }
+
+    pub(crate) const fn at(self, offset: usize, index: usize) -> DecodeError {
+        Self {
+            offset: self.offset + offset,
+            index,
+            reason: self.reason,
+        }
+    }
+}
+
+impl Display for DecodeReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            DecodeReason::UnknownOpCode(byte) => write!(f, "unknown opcode {:#04x}", byte),
+            DecodeReason::UnknownArgTag(byte) => write!(f, "unknown argument tag {:#04x}", byte),
+            DecodeReason::InvalidValue => write!(f, "invalid argument value"),
+            DecodeReason::Truncated => write!(f, "truncated instruction"),
+            DecodeReason::TrailingBytes(count) => write!(f, "{} trailing bytes", count),
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "decode error at byte {} (instruction #{}): {}",
+            self.offset, self.index, self.reason
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum AssembleErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    InvalidArgument(String),
+    InvalidLabel(String),
+    InvalidRegister(String),
+    MissingArgument,
+    UnexpectedToken(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            AssembleErrorKind::UnknownMnemonic(text) => write!(f, "unknown mnemonic `{}`", text),
+            AssembleErrorKind::UnknownDirective(text) => {
+                write!(f, "unknown directive `{}`", text)
+            }
+            AssembleErrorKind::InvalidArgument(text) => write!(f, "invalid argument `{}`", text),
+            AssembleErrorKind::InvalidLabel(text) => write!(f, "invalid label name `{}`", text),
+            AssembleErrorKind::InvalidRegister(text) => write!(f, "invalid register `{}`", text),
+            AssembleErrorKind::MissingArgument => write!(f, "missing argument"),
+            AssembleErrorKind::UnexpectedToken(text) => write!(f, "unexpected `{}`", text),
+            AssembleErrorKind::DuplicateLabel(name) => {
+                write!(f, "label `{}` is already defined", name)
+            }
+            AssembleErrorKind::UndefinedLabel(name) => write!(f, "undefined label `{}`", name),
+        }
+    }
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl Error for AssembleError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BuildError {
+    UnboundLabel(String),
+    LabelRebound(String),
+    DuplicateLabel(String),
+    ImportBound(String),
+    ForeignLabel,
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            BuildError::UnboundLabel(name) => write!(f, "label `{}` is used but never bound", name),
+            BuildError::LabelRebound(name) => write!(f, "label `{}` is bound more than once", name),
+            BuildError::DuplicateLabel(name) => {
+                write!(f, "label `{}` is declared more than once", name)
+            }
+            BuildError::ImportBound(name) => write!(f, "imported label `{}` cannot be bound", name),
+            BuildError::ForeignLabel => write!(f, "label belongs to a different builder"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum LinkError {
+    UndefinedSymbol {
+        name: String,
+        module: String,
+    },
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    InvalidRelocation {
+        module: String,
+        pc: usize,
+    },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            LinkError::UndefinedSymbol { name, module } => {
+                write!(
+                    f,
+                    "undefined symbol `{}` imported by module `{}`",
+                    name, module
+                )
+            }
+            LinkError::DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "symbol `{}` is defined by both `{}` and `{}`",
+                name, first, second
+            ),
+            LinkError::InvalidRelocation { module, pc } => {
+                write!(f, "invalid relocation at #{} in module `{}`", pc, module)
+            }
+        }
+    }
+}
+
+impl Error for LinkError {}
@@ -1,4 +1,4 @@
-use tinyvm::op::OpArg::{Instruction, Register1, Register2, Register3, Register4, Uint64};
+use tinyvm::op::OpArg::{Register1, Register2, Register3, Register4, Uint64};
 use tinyvm::op::OpCode::{Add, Exit, Jump, JumpIfZero, Pop, Push, Subtract};
 use tinyvm::program::Program;
 use tinyvm::{op, program_static};
@@ -12,10 +12,11 @@ pub static FIB: Program = program_static!(
     // fib(1) = 1: store in r2.
     op!(Push, Uint64(1)),
     op!(Pop, Register2),
-    // Pushes the counter-value. (loop start: instruction 5)
+    // Pushes the counter-value.
+    loop_start:
     op!(Push, Register3),
     // Exit loop if counter-value == 0.
-    op!(JumpIfZero, Instruction(20)),
+    op!(JumpIfZero, done),
     // Calculate next fibonacci: next = r1 + r2
     op!(Push, Register1),
     op!(Push, Register2),
@@ -33,8 +34,9 @@ pub static FIB: Program = program_static!(
     op!(Subtract),
     op!(Pop, Register3),
     // Jump back to the loop start.
-    op!(Jump, Instruction(5)),
+    op!(Jump, loop_start),
     // Push the result to the stack.
+    done:
     op!(Push, Register2),
     // Exit.
     op!(Exit)
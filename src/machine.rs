@@ -1,9 +1,28 @@
-use crate::error::{MachineError, Result};
+use crate::error::{Fault, MachineError, Result};
+use crate::machine::host::AsyncHost;
+use crate::machine::interrupt::InterruptHandle;
+use crate::machine::journal::Journal;
+use crate::machine::observer::{MachineObserver, NoObserver};
+use crate::machine::record::{Event, Outcome, Recording};
 use crate::machine::value::MachineValue;
+use crate::machine::watch::Watchpoint;
 use crate::op::{Op, OpArg, OpCode};
-use crate::program::Program;
+use crate::program::view::ProgramView;
+use crate::program::{Program, fingerprint};
+use std::collections::BTreeMap;
 
+pub mod coverage;
+pub mod debug;
+pub mod host;
+pub mod interrupt;
+pub mod journal;
+pub mod observer;
+pub mod profile;
+pub mod record;
+pub mod snapshot;
+pub mod trace;
 pub mod value;
+pub mod watch;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
 pub struct RegisterBank {
@@ -19,6 +38,23 @@ impl RegisterBank {
         self.registers = [MachineValue::None; 9];
     }
 
+    pub fn registers(&self) -> &[MachineValue] {
+        &self.registers
+    }
+
+    pub fn get(&self, index: usize) -> Option<MachineValue> {
+        self.registers.get(index).copied()
+    }
+
+    pub fn set(&mut self, index: usize, value: MachineValue) -> Result<()> {
+        let register = self
+            .registers
+            .get_mut(index)
+            .ok_or(MachineError::RegisterExpected)?;
+        *register = value;
+        Ok(())
+    }
+
     #[inline]
     pub fn load(&self, arg: OpArg) -> Option<MachineValue> {
         match arg {
@@ -53,29 +89,312 @@ impl RegisterBank {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Handler {
+    pub target: usize,
+    pub stack: usize,
+    pub calls: usize,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Frame {
+    pub call_site: usize,
+    pub return_address: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MachineState<'machine> {
+    pub program: &'machine Program,
+    pub stack: &'machine [MachineValue],
+    pub calls: &'machine [MachineValue],
+    pub bank: &'machine RegisterBank,
+    pub current: usize,
+    code: Code<'machine>,
+}
+
+impl MachineState<'_> {
+    pub fn op(&self, pc: usize) -> Option<Op> {
+        self.code.get(pc)
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.len() == 0
+    }
+}
+
+pub type BreakpointCondition = fn(&MachineState<'_>) -> bool;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Code<'program> {
+    Ops(&'program [Op]),
+    View(&'program ProgramView<'program>),
+}
+
+impl Code<'_> {
+    fn get(&self, pc: usize) -> Option<Op> {
+        match self {
+            Code::Ops(ops) => ops.get(pc).copied(),
+            Code::View(view) => view.get(pc),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Code::Ops(ops) => ops.len(),
+            Code::View(view) => view.len(),
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        match self {
+            Code::Ops(ops) => fingerprint(ops.iter().copied()),
+            Code::View(view) => view.fingerprint(),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
-pub struct Machine<'program> {
+pub struct Machine<'program, O = NoObserver> {
     program: &'program Program,
+    code: Code<'program>,
     stack: Vec<MachineValue>,
     calls: Vec<MachineValue>,
     bank: RegisterBank,
     current: usize,
+    handlers: Vec<Handler>,
+    catch_faults: bool,
+    interrupt: InterruptHandle,
+    breakpoints: BTreeMap<usize, Option<BreakpointCondition>>,
+    paused: Option<usize>,
+    pending: Option<MachineLoopState>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_watchpoint: usize,
+    journal: Option<Box<Journal>>,
+    recording: Option<Box<Recording>>,
+    observer: O,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MachineLoopState {
     Continue,
     Break,
+    Native(u64),
+    Interrupted,
+    Breakpoint(usize),
+    Watchpoint { id: usize, pc: usize },
 }
 
 impl<'program> Machine<'program> {
     pub fn new(program: &'program Program) -> Machine<'program> {
+        Self::with_observer(program, NoObserver)
+    }
+
+    pub fn from_view(view: &'program ProgramView<'program>) -> Machine<'program> {
+        Self::view_with_observer(view, NoObserver)
+    }
+}
+
+impl<'program, O: MachineObserver> Machine<'program, O> {
+    pub fn with_observer(program: &'program Program, observer: O) -> Machine<'program, O> {
+        Self::with_code(program, Code::Ops(program.ops()), observer)
+    }
+
+    pub fn view_with_observer(
+        view: &'program ProgramView<'program>,
+        observer: O,
+    ) -> Machine<'program, O> {
+        Self::with_code(view.metadata(), Code::View(view), observer)
+    }
+
+    fn with_code(program: &'program Program, code: Code<'program>, observer: O) -> Self {
         Self {
             program,
+            code,
             stack: Vec::new(),
             calls: Vec::new(),
             bank: RegisterBank::new(),
             current: 0,
+            handlers: Vec::new(),
+            catch_faults: false,
+            interrupt: InterruptHandle::new(),
+            breakpoints: BTreeMap::new(),
+            paused: None,
+            pending: None,
+            watchpoints: BTreeMap::new(),
+            next_watchpoint: 0,
+            journal: None,
+            recording: None,
+            observer,
+        }
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
+
+    pub fn state(&self) -> MachineState<'_> {
+        MachineState {
+            program: self.program,
+            stack: &self.stack,
+            calls: &self.calls,
+            bank: &self.bank,
+            current: self.current,
+            code: self.code,
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.current
+    }
+
+    pub fn stack(&self) -> &[MachineValue] {
+        &self.stack
+    }
+
+    pub fn calls(&self) -> impl DoubleEndedIterator<Item = Frame> + '_ {
+        self.calls.iter().filter_map(|value| match value {
+            MachineValue::ReturnAddress(address) => Some(Frame {
+                call_site: address.saturating_sub(1),
+                return_address: *address,
+            }),
+            _ => None,
+        })
+    }
+
+    pub fn registers(&self) -> &RegisterBank {
+        &self.bank
+    }
+
+    pub fn register(&self, index: usize) -> Option<MachineValue> {
+        self.bank.get(index)
+    }
+
+    pub fn peek(&self, depth: usize) -> Result<MachineValue> {
+        self.stack
+            .iter()
+            .rev()
+            .nth(depth)
+            .copied()
+            .ok_or(MachineError::StackEmpty)
+    }
+
+    pub fn set_pc(&mut self, pc: usize) -> Result<()> {
+        if pc >= self.code.len() {
+            return Err(MachineError::InstructionOverflow);
+        }
+        if self.recording.is_some() {
+            self.record(|_| Event::SetPc(pc));
+        }
+        self.current = pc;
+        self.paused = None;
+        self.pending = None;
+        Ok(())
+    }
+
+    pub fn set_register(&mut self, index: usize, value: MachineValue) -> Result<()> {
+        let previous = self.bank.get(index);
+        self.bank.set(index, value)?;
+        if let (Some(journal), Some(previous)) = (&mut self.journal, previous) {
+            journal.overwritten(index, previous);
+        }
+        if self.recording.is_some() {
+            self.record(|_| Event::SetRegister(index, value));
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn observe(&mut self, f: impl FnOnce(&mut O, &MachineState<'_>)) {
+        let state = MachineState {
+            program: self.program,
+            stack: &self.stack,
+            calls: &self.calls,
+            bank: &self.bank,
+            current: self.current,
+            code: self.code,
+        };
+        f(&mut self.observer, &state);
+    }
+
+    pub fn set_catch_faults(&mut self, catch_faults: bool) {
+        self.catch_faults = catch_faults;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn set_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc, None);
+    }
+
+    pub fn set_conditional_breakpoint(&mut self, pc: usize, condition: BreakpointCondition) {
+        self.breakpoints.insert(pc, Some(condition));
+    }
+
+    pub fn clear_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc).is_some()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.insert(id, watchpoint);
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        self.watchpoints.remove(&id)
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    #[inline]
+    fn instrumented(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.journal.is_some()
+            || self.recording.is_some()
+            || self.pending.is_some()
+    }
+
+    fn poll_breakpoint(&mut self) -> Option<MachineLoopState> {
+        let pc = self.current;
+        if self.paused.take() == Some(pc) {
+            return None;
+        }
+        let condition = self.breakpoints.get(&pc)?;
+        if condition.is_some_and(|condition| !condition(&self.state())) {
+            return None;
+        }
+        self.paused = Some(pc);
+        Some(MachineLoopState::Breakpoint(pc))
+    }
+
+    #[inline]
+    fn poll_interrupt(&self, origin: usize) -> MachineLoopState {
+        if self.current <= origin && self.interrupt.take() {
+            MachineLoopState::Interrupted
+        } else {
+            MachineLoopState::Continue
         }
     }
 
@@ -88,72 +407,211 @@ impl<'program> Machine<'program> {
     }
 
     fn call(&mut self, op: &Op) -> Result<()> {
-        let current = self.current + 1;
+        let origin = self.current;
         self.jmp(op)?;
-        self.calls.push(MachineValue::ReturnAddress(current));
+        self.calls.push(MachineValue::ReturnAddress(origin + 1));
+        self.observer.on_call(origin, self.current);
         Ok(())
     }
 
     fn ret(&mut self) -> Result<()> {
-        let value = self.calls.pop().ok_or(MachineError::StackEmpty)?;
+        let origin = self.current;
+        let Some(value) = self.calls.pop() else {
+            return Err(MachineError::CallStackEmpty);
+        };
         self.current = match value {
             MachineValue::ReturnAddress(value) => value,
             _ => return Err(MachineError::InstructionExpected),
         };
+        self.observer.on_return(origin, self.current);
+        while let Some(handler) = self.handlers.last() {
+            if handler.calls <= self.calls.len() {
+                break;
+            }
+            self.handlers.pop();
+        }
+        Ok(())
+    }
+
+    fn try_begin(&mut self, op: &Op) -> Result<()> {
+        let target = match op.arg {
+            OpArg::Instruction(instruction) => instruction as usize,
+            _ => return Err(MachineError::InstructionExpected),
+        };
+        self.handlers.push(Handler {
+            target,
+            stack: self.stack.len(),
+            calls: self.calls.len(),
+        });
+        Ok(())
+    }
+
+    fn throw(&mut self, value: MachineValue) -> Result<()> {
+        let handler = self.handlers.pop().ok_or(MachineError::Uncaught(value))?;
+        self.stack.truncate(handler.stack);
+        self.calls.truncate(handler.calls);
+        self.stack.push(value);
+        self.current = handler.target;
         Ok(())
     }
 
     pub fn step(&mut self) -> Result<MachineLoopState> {
-        let op = self
-            .program
-            .ops()
-            .get(self.current)
-            .ok_or(MachineError::InstructionOverflow)?;
+        if let Some(state) = self.pending.take() {
+            return Ok(state);
+        }
+        if self.watchpoints.is_empty() {
+            self.step_unwatched()
+        } else {
+            self.step_watched()
+        }
+    }
+
+    fn step_unwatched(&mut self) -> Result<MachineLoopState> {
+        let result = if self.journal.is_some() {
+            self.step_journaled()
+        } else {
+            self.advance()
+        };
+        if self.recording.is_some() {
+            self.record(|machine| Event::Step {
+                outcome: Outcome::of(&result),
+                digest: machine.digest(),
+            });
+        }
+        result
+    }
+
+    fn advance(&mut self) -> Result<MachineLoopState> {
+        let pc = self.current;
+        match self.execute(pc) {
+            Err(error) => self.fault(pc, error),
+            result => result,
+        }
+    }
+
+    #[cold]
+    fn fault(&mut self, pc: usize, error: MachineError) -> Result<MachineLoopState> {
+        self.observer.on_error(pc, &error);
+        if !self.catch_faults || self.handlers.is_empty() {
+            return Err(self.trace(pc, error));
+        }
+        self.throw(MachineValue::Fault(error.code()))
+            .map_err(|error| self.trace(pc, error))?;
+        Ok(self.poll_interrupt(pc))
+    }
+
+    fn trace(&self, pc: usize, error: MachineError) -> MachineError {
+        let backtrace = self
+            .calls
+            .iter()
+            .rev()
+            .map(|value| match value {
+                MachineValue::ReturnAddress(address) => *address,
+                value => value.as_u64() as usize,
+            })
+            .collect();
+        MachineError::Fault(Box::new(Fault {
+            error,
+            pc,
+            op: self.code.get(pc),
+            depth: self.stack.len(),
+            backtrace,
+        }))
+    }
+
+    #[inline(always)]
+    fn execute(&mut self, pc: usize) -> Result<MachineLoopState> {
+        match self.code {
+            Code::Ops(ops) => match ops.get(pc) {
+                Some(op) => self.perform(pc, op),
+                None => Err(MachineError::InstructionOverflow),
+            },
+            Code::View(view) => match view.get(pc) {
+                Some(op) => self.perform(pc, &op),
+                None => Err(MachineError::InstructionOverflow),
+            },
+        }
+    }
+
+    #[inline(always)]
+    fn perform(&mut self, pc: usize, op: &Op) -> Result<MachineLoopState> {
+        self.observe(|observer, state| observer.before_step(pc, op, state));
+        let state = self.dispatch(op)?;
+        self.observe(|observer, state| observer.after_step(pc, op, state));
+        Ok(state)
+    }
+
+    #[inline(always)]
+    fn dispatch(&mut self, op: &Op) -> Result<MachineLoopState> {
         match op.code {
             OpCode::Push => {
-                let value =
-                    MachineValue::of(op.arg, &self.bank).ok_or(MachineError::ValueExpected)?;
+                let Some(value) = MachineValue::of(op.arg, &self.bank) else {
+                    return Err(MachineError::ValueExpected);
+                };
                 self.stack.push(value);
             }
 
             OpCode::Pop => {
-                let value = self.stack.pop().ok_or(MachineError::StackEmpty)?;
-                self.bank.store(op.arg, value)?;
+                let value = self.pop_stack()?;
+                if let Err(error) = self.bank.store(op.arg, value) {
+                    return Err(self.unpop(&[value], error));
+                }
             }
 
             OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
-                let value1 = self.pop()?;
-                let value2 = self.pop()?;
+                let value1 = self.pop_stack()?;
+                let Some(value2) = self.stack.pop() else {
+                    return Err(self.unpop(&[value1], MachineError::StackEmpty));
+                };
                 let result = match op.code {
                     OpCode::Add => value2 + value1,
                     OpCode::Subtract => value2 - value1,
                     OpCode::Multiply => value2 * value1,
-                    OpCode::Divide => value2 / value1,
+                    OpCode::Divide => match value2.checked_div(value1) {
+                        Some(value) => value,
+                        None if value2.zero() == value1 => {
+                            return Err(self.unpop(&[value2, value1], MachineError::DivisionByZero));
+                        }
+                        None => return Err(self.unpop(&[value2, value1], MachineError::Overflow)),
+                    },
                     _ => unreachable!("operation invalid"),
                 };
                 self.stack.push(result);
             }
 
             OpCode::JumpIfEqual => {
-                let value1 = self.pop()?;
-                let value2 = self.pop()?;
+                let value1 = self.pop_stack()?;
+                let Some(value2) = self.stack.pop() else {
+                    return Err(self.unpop(&[value1], MachineError::StackEmpty));
+                };
                 if value1 == value2 {
-                    self.jmp(op)?;
-                    return Ok(MachineLoopState::Continue);
+                    let origin = self.current;
+                    if let Err(error) = self.jmp(op) {
+                        return Err(self.unpop(&[value2, value1], error));
+                    }
+                    self.observer.on_branch(origin, true);
+                    return Ok(self.poll_interrupt(origin));
                 }
+                self.observer.on_branch(self.current, false);
             }
 
             OpCode::Jump => {
+                let origin = self.current;
                 self.jmp(op)?;
-                return Ok(MachineLoopState::Continue);
+                return Ok(self.poll_interrupt(origin));
             }
 
             OpCode::JumpIfZero => {
-                let value = self.stack.pop().ok_or(MachineError::StackEmpty)?;
+                let value = self.pop_stack()?;
                 if value.as_u64() == 0 {
-                    self.jmp(op)?;
-                    return Ok(MachineLoopState::Continue);
+                    let origin = self.current;
+                    if let Err(error) = self.jmp(op) {
+                        return Err(self.unpop(&[value], error));
+                    }
+                    self.observer.on_branch(origin, true);
+                    return Ok(self.poll_interrupt(origin));
                 }
+                self.observer.on_branch(self.current, false);
             }
 
             OpCode::Exit => {
@@ -162,32 +620,164 @@ impl<'program> Machine<'program> {
 
             OpCode::Call => {
                 self.call(op)?;
-                return Ok(MachineLoopState::Continue);
+                return Ok(self.poll_interrupt(usize::MAX));
             }
 
             OpCode::Return => {
                 self.ret()?;
                 return Ok(MachineLoopState::Continue);
             }
+
+            OpCode::Native => {
+                let Some(id) = MachineValue::of(op.arg, &self.bank) else {
+                    return Err(MachineError::ValueExpected);
+                };
+                self.current += 1;
+                return Ok(MachineLoopState::Native(id.as_u64()));
+            }
+
+            OpCode::Throw => {
+                let value = match op.arg {
+                    OpArg::None => {
+                        let value = self.pop_stack()?;
+                        if self.handlers.is_empty() {
+                            return Err(self.unpop(&[value], MachineError::Uncaught(value)));
+                        }
+                        value
+                    }
+                    arg => match MachineValue::of(arg, &self.bank) {
+                        Some(value) => value,
+                        None => return Err(MachineError::ValueExpected),
+                    },
+                };
+                let origin = self.current;
+                self.throw(value)?;
+                return Ok(self.poll_interrupt(origin));
+            }
+
+            OpCode::TryBegin => {
+                self.try_begin(op)?;
+            }
+
+            OpCode::TryEnd => match self.handlers.last() {
+                Some(handler) if handler.calls == self.calls.len() => {
+                    self.handlers.pop();
+                }
+                _ => return Err(MachineError::HandlerStackEmpty),
+            },
         }
         self.current += 1;
         Ok(MachineLoopState::Continue)
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        while let MachineLoopState::Continue = self.step()? {}
-        Ok(())
+    pub fn run(&mut self) -> Result<MachineLoopState> {
+        if self.instrumented() {
+            return self.run_instrumented();
+        }
+        loop {
+            let pc = self.current;
+            let state = match self.execute(pc) {
+                Ok(state) => state,
+                Err(error) => self.fault(pc, error)?,
+            };
+            if state != MachineLoopState::Continue {
+                return Ok(state);
+            }
+        }
+    }
+
+    fn run_instrumented(&mut self) -> Result<MachineLoopState> {
+        loop {
+            match self.step_instrumented()? {
+                MachineLoopState::Continue => {}
+                state => return Ok(state),
+            }
+        }
+    }
+
+    fn step_instrumented(&mut self) -> Result<MachineLoopState> {
+        if let Some(state) = self.pending.take() {
+            return Ok(state);
+        }
+        if let Some(state) = self.poll_breakpoint() {
+            return Ok(state);
+        }
+        self.step()
+    }
+
+    fn step_watched(&mut self) -> Result<MachineLoopState> {
+        let pc = self.current;
+        let depth = self.stack.len();
+        let bank = self.bank;
+        let written = self
+            .code
+            .get(pc)
+            .filter(|op| op.code == OpCode::Pop)
+            .map(|op| op.arg);
+        let state = self.step_unwatched()?;
+        for (id, watchpoint) in &self.watchpoints {
+            if watchpoint.triggered(written, &bank, &self.bank, (depth, self.stack.len())) {
+                if state != MachineLoopState::Continue {
+                    self.pending = Some(state);
+                }
+                return Ok(MachineLoopState::Watchpoint { id: *id, pc });
+            }
+        }
+        Ok(state)
+    }
+
+    pub async fn run_async<H: AsyncHost>(&mut self, host: &mut H) -> Result<MachineLoopState> {
+        loop {
+            let state = if self.instrumented() {
+                self.step_instrumented()?
+            } else {
+                self.step()?
+            };
+            match state {
+                MachineLoopState::Continue => {}
+                MachineLoopState::Native(id) => host.call(id, self).await?,
+                state => return Ok(state),
+            }
+        }
+    }
+
+    #[cold]
+    fn unpop(&mut self, operands: &[MachineValue], error: MachineError) -> MachineError {
+        self.stack.extend_from_slice(operands);
+        error
+    }
+
+    #[inline(always)]
+    fn pop_stack(&mut self) -> Result<MachineValue> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(MachineError::StackEmpty),
+        }
     }
 
     pub fn push(&mut self, value: MachineValue) {
+        if self.recording.is_some() {
+            self.record(|_| Event::Push(value));
+        }
         self.stack.push(value);
     }
 
     pub fn pop(&mut self) -> Result<MachineValue> {
-        self.stack.pop().ok_or(MachineError::StackEmpty)
+        let value = self.stack.pop();
+        if let (Some(journal), Some(value)) = (&mut self.journal, value) {
+            journal.popped(self.stack.len(), value);
+        }
+        if self.recording.is_some() {
+            self.record(|_| Event::Pop(value));
+        }
+        value.ok_or(MachineError::StackEmpty)
     }
 
     pub fn reset(&mut self) {
+        if self.recording.is_some() {
+            self.record(|_| Event::Reset);
+        }
+
         if !self.stack.is_empty() {
             self.stack.clear();
         }
@@ -196,7 +786,277 @@ impl<'program> Machine<'program> {
             self.calls.clear();
         }
 
+        if !self.handlers.is_empty() {
+            self.handlers.clear();
+        }
+
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+
         self.current = 0;
+        self.paused = None;
+        self.pending = None;
         self.bank.reset();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Machine, MachineLoopState};
+    use crate::error::MachineError;
+    use crate::machine::value::MachineValue;
+    use crate::machine::watch::Watchpoint;
+    use crate::op::OpArg::{Int64, Register1, Uint32, Uint64};
+    use crate::op::OpCode::{
+        Call, Divide, Exit, JumpIfZero, Native, Pop, Push, Return, Throw, TryBegin, TryEnd,
+    };
+    use crate::{op, program};
+
+    #[test]
+    fn caught_faults_are_distinct_from_thrown_values() {
+        let program = program!(
+            op!(TryBegin, handler),
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(0)),
+            op!(Divide),
+            op!(TryEnd),
+            op!(Throw, Uint32(8)),
+            handler:
+            op!(Exit),
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_catch_faults(true);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        let fault = MachineValue::Fault(MachineError::DivisionByZero.code());
+        assert!(machine.stack()[0].identical(fault));
+        assert_ne!(machine.stack()[0], MachineValue::Uint32(8));
+    }
+
+    #[test]
+    fn signed_division_overflow_is_not_division_by_zero() {
+        let program = program!(
+            op!(Push, Int64(i64::MIN)),
+            op!(Push, Int64(-1)),
+            op!(Divide)
+        );
+        let mut machine = Machine::new(&program);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.root(), &MachineError::Overflow);
+
+        let program = program!(op!(Push, Int64(i64::MIN)), op!(Push, Int64(0)), op!(Divide));
+        let mut machine = Machine::new(&program);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.root(), &MachineError::DivisionByZero);
+    }
+
+    #[test]
+    fn throwing_backward_polls_for_interrupts() {
+        let program = program!(
+            start:
+            op!(TryBegin, start),
+            op!(Throw, Uint32(1)),
+        );
+        let mut machine = Machine::new(&program);
+        machine.interrupt_handle().interrupt();
+        assert_eq!(machine.run(), Ok(MachineLoopState::Interrupted));
+        assert_eq!(machine.pc(), 0);
+        assert_eq!(machine.stack(), [MachineValue::Uint32(1)]);
+
+        let program = program!(
+            start:
+            op!(TryBegin, start),
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(0)),
+            op!(Divide),
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_catch_faults(true);
+        machine.interrupt_handle().interrupt();
+        assert_eq!(machine.run(), Ok(MachineLoopState::Interrupted));
+        assert_eq!(machine.pc(), 0);
+    }
+
+    #[test]
+    fn try_end_leaves_handlers_of_calling_frames_alone() {
+        let program = program!(
+            op!(TryBegin, handler),
+            op!(Call, callee),
+            op!(Exit),
+            handler:
+            op!(Exit),
+            callee:
+            op!(TryEnd),
+            op!(Return),
+        );
+        let mut machine = Machine::new(&program);
+        let MachineError::Fault(fault) = machine.run().unwrap_err() else {
+            panic!("expected a fault");
+        };
+        assert_eq!(
+            (fault.pc, fault.error),
+            (4, MachineError::HandlerStackEmpty)
+        );
+        assert_eq!(machine.handlers.len(), 1);
+
+        let program = program!(
+            op!(TryBegin, handler),
+            op!(Call, callee),
+            op!(Throw, Uint32(7)),
+            handler:
+            op!(Exit),
+            callee:
+            op!(TryBegin, handler),
+            op!(TryEnd),
+            op!(Return),
+        );
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(machine.pc(), 3);
+        assert_eq!(machine.stack(), [MachineValue::Uint32(7)]);
+    }
+
+    #[test]
+    fn accessors_check_their_bounds() {
+        let program = program!(
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(2)),
+            op!(Call, callee),
+            op!(Exit),
+            callee:
+            op!(Native, Uint64(0)),
+            op!(Return),
+        );
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.peek(0), Err(MachineError::StackEmpty));
+        assert_eq!(machine.run(), Ok(MachineLoopState::Native(0)));
+
+        assert_eq!(machine.pc(), 5);
+        assert_eq!(
+            machine.stack(),
+            [MachineValue::Uint64(1), MachineValue::Uint64(2)]
+        );
+        assert_eq!(machine.peek(0), Ok(MachineValue::Uint64(2)));
+        assert_eq!(machine.peek(1), Ok(MachineValue::Uint64(1)));
+        assert_eq!(machine.peek(2), Err(MachineError::StackEmpty));
+        assert_eq!(machine.peek(usize::MAX), Err(MachineError::StackEmpty));
+        let frames: Vec<_> = machine.calls().collect();
+        assert_eq!(
+            frames,
+            [Frame {
+                call_site: 2,
+                return_address: 3,
+            }]
+        );
+
+        assert_eq!(machine.register(0), Some(MachineValue::None));
+        assert_eq!(machine.set_register(0, MachineValue::Int64(-3)), Ok(()));
+        assert_eq!(machine.register(0), Some(MachineValue::Int64(-3)));
+        assert_eq!(
+            machine.registers().load(Register1),
+            Some(MachineValue::Int64(-3))
+        );
+        assert_eq!(machine.register(9), None);
+        assert_eq!(
+            machine.set_register(9, MachineValue::Uint64(0)),
+            Err(MachineError::RegisterExpected)
+        );
+
+        assert_eq!(machine.set_pc(6), Err(MachineError::InstructionOverflow));
+        assert_eq!(
+            machine.set_pc(usize::MAX),
+            Err(MachineError::InstructionOverflow)
+        );
+        assert_eq!(machine.pc(), 5);
+        assert_eq!(machine.set_pc(3), Ok(()));
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(machine.pc(), 3);
+    }
+
+    #[test]
+    fn breakpoints_stop_once_and_respect_conditions() {
+        let program = program!(
+            op!(Push, Uint64(1)),
+            op!(Pop, Register1),
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, next),
+            next:
+            op!(Push, Uint64(2)),
+            op!(Exit),
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_breakpoint(1);
+        machine.set_conditional_breakpoint(2, |state| {
+            state.bank.registers[0] == MachineValue::Uint64(2)
+        });
+        machine.set_conditional_breakpoint(4, |state| {
+            state.bank.registers[0] == MachineValue::Uint64(1)
+        });
+        assert_eq!(machine.run(), Ok(MachineLoopState::Breakpoint(1)));
+        assert_eq!(machine.pc(), 1);
+        assert_eq!(machine.stack(), [MachineValue::Uint64(1)]);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Breakpoint(4)));
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(machine.stack(), [MachineValue::Uint64(2)]);
+
+        assert!(machine.clear_breakpoint(1));
+        assert!(!machine.clear_breakpoint(1));
+        machine.reset();
+        assert_eq!(machine.run(), Ok(MachineLoopState::Breakpoint(4)));
+    }
+
+    fn fault_of(program: &crate::program::Program) -> (usize, Vec<MachineValue>) {
+        let mut machine = Machine::new(program);
+        let MachineError::Fault(fault) = machine.run().unwrap_err() else {
+            panic!("expected a fault");
+        };
+        (fault.depth, machine.stack().to_vec())
+    }
+
+    #[test]
+    fn faults_report_the_depth_before_the_operands_were_popped() {
+        let program = program!(op!(Push, Uint64(1)), op!(Push, Uint64(0)), op!(Divide));
+        let (depth, stack) = fault_of(&program);
+        assert_eq!(depth, 2);
+        assert_eq!(stack, [MachineValue::Uint64(1), MachineValue::Uint64(0)]);
+
+        let program = program!(op!(Push, Uint64(0)), op!(JumpIfZero, Uint64(3)));
+        assert_eq!(fault_of(&program).0, 1);
+
+        let program = program!(op!(Push, Uint64(1)), op!(Pop, Uint64(3)));
+        assert_eq!(fault_of(&program).0, 1);
+
+        let program = program!(op!(Push, Uint64(7)), op!(Throw));
+        assert_eq!(fault_of(&program).0, 1);
+    }
+
+    #[test]
+    fn watchpoints_fire_when_stepping() {
+        let program = program!(op!(Push, Uint64(1)), op!(Push, Uint64(2)), op!(Exit));
+        let mut machine = Machine::new(&program);
+        let id = machine.add_watchpoint(Watchpoint::StackDepth(1));
+        assert_eq!(machine.step(), Ok(MachineLoopState::Continue));
+        assert_eq!(
+            machine.step(),
+            Ok(MachineLoopState::Watchpoint { id, pc: 1 })
+        );
+        assert_eq!(machine.step(), Ok(MachineLoopState::Break));
+    }
+
+    #[test]
+    fn watchpoints_do_not_swallow_interrupts() {
+        let program = program!(
+            start:
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, start),
+        );
+        let mut machine = Machine::new(&program);
+        let id = machine.add_watchpoint(Watchpoint::StackDepth(0));
+        machine.interrupt_handle().interrupt();
+        for pc in [0, 1] {
+            assert_eq!(machine.run(), Ok(MachineLoopState::Watchpoint { id, pc }));
+        }
+        assert_eq!(machine.run(), Ok(MachineLoopState::Interrupted));
+        assert_eq!(machine.pc(), 0);
+    }
+}
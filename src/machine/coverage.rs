@@ -0,0 +1,239 @@
+use crate::machine::MachineState;
+use crate::machine::observer::MachineObserver;
+use crate::op::{Op, OpCode};
+use crate::program::Program;
+use std::collections::BTreeMap;
+use std::io::{Result as IoResult, Write};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Coverage {
+    executed: Vec<u64>,
+    branches: BTreeMap<usize, Branch>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Self::default()
+    }
+
+    pub fn executed(&self, pc: usize) -> u64 {
+        self.executed.get(pc).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, pc: usize) -> Option<Branch> {
+        self.branches.get(&pc).copied()
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        if other.executed.len() > self.executed.len() {
+            self.executed.resize(other.executed.len(), 0);
+        }
+        for (pc, count) in other.executed.iter().enumerate() {
+            self.executed[pc] += count;
+        }
+        for (pc, branch) in &other.branches {
+            let entry = self.branches.entry(*pc).or_default();
+            entry.taken += branch.taken;
+            entry.not_taken += branch.not_taken;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.executed.clear();
+        self.branches.clear();
+    }
+
+    pub fn write_lcov(
+        &self,
+        program: &Program,
+        source: &str,
+        writer: &mut impl Write,
+    ) -> IoResult<()> {
+        writeln!(writer, "TN:")?;
+        writeln!(writer, "SF:{}", source)?;
+
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut branches = Vec::new();
+        for (pc, op) in program.iter().enumerate() {
+            let line = program.line(pc).map_or(pc + 1, |info| info.line as usize);
+            let count = self.executed(pc);
+            let hits = lines.entry(line).or_insert(0);
+            *hits = (*hits).max(count);
+            if is_conditional(op.code) {
+                branches.push((line, pc, count, self.branch(pc).unwrap_or_default()));
+            }
+        }
+
+        let mut hit = 0;
+        for (line, pc, count, branch) in &branches {
+            for (index, taken) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                if *count == 0 {
+                    writeln!(writer, "BRDA:{},{},{},-", line, pc, index)?;
+                } else {
+                    writeln!(writer, "BRDA:{},{},{},{}", line, pc, index, taken)?;
+                }
+                hit += (taken > 0) as usize;
+            }
+        }
+        writeln!(writer, "BRF:{}", branches.len() * 2)?;
+        writeln!(writer, "BRH:{}", hit)?;
+
+        for (line, count) in &lines {
+            writeln!(writer, "DA:{},{}", line, count)?;
+        }
+        writeln!(writer, "LF:{}", lines.len())?;
+        writeln!(
+            writer,
+            "LH:{}",
+            lines.values().filter(|count| **count > 0).count()
+        )?;
+        writeln!(writer, "end_of_record")
+    }
+}
+
+impl MachineObserver for Coverage {
+    fn before_step(&mut self, pc: usize, _op: &Op, _state: &MachineState<'_>) {
+        if pc >= self.executed.len() {
+            self.executed.resize(pc + 1, 0);
+        }
+        self.executed[pc] += 1;
+    }
+
+    fn on_branch(&mut self, pc: usize, taken: bool) {
+        let branch = self.branches.entry(pc).or_default();
+        if taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+}
+
+fn is_conditional(code: OpCode) -> bool {
+    matches!(code, OpCode::JumpIfZero | OpCode::JumpIfEqual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Branch, Coverage};
+    use crate::machine::Machine;
+    use crate::op::OpArg::Uint64;
+    use crate::op::OpCode::{Exit, JumpIfEqual, JumpIfZero, Push};
+    use crate::program::Program;
+    use crate::{op, program};
+
+    const SOURCE: &str = "\
+.func main
+    push 0u64
+    jz skip
+    push 1u64
+skip:
+    push 1u64
+    .line 6 9
+    push 2u64
+    jeq done
+    jmp done
+    jz done
+done:
+    exit
+";
+
+    #[test]
+    fn jumps_to_the_next_instruction_count_as_taken() {
+        let program = program!(
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, next),
+            next:
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(2)),
+            op!(JumpIfEqual, done),
+            done:
+            op!(Exit),
+        );
+        let mut coverage = Coverage::new();
+        let mut machine = Machine::with_observer(&program, &mut coverage);
+        machine.run().unwrap();
+        assert_eq!(
+            coverage.branch(1),
+            Some(Branch {
+                taken: 1,
+                not_taken: 0
+            })
+        );
+        assert_eq!(
+            coverage.branch(4),
+            Some(Branch {
+                taken: 0,
+                not_taken: 1
+            })
+        );
+    }
+
+    #[test]
+    fn lcov_reports_lines_and_branches() {
+        let program = Program::assemble_file("main.tvs", SOURCE).unwrap();
+        let mut coverage = Coverage::new();
+        let mut machine = Machine::with_observer(&program, &mut coverage);
+        machine.run().unwrap();
+        let mut lcov = Vec::new();
+        coverage
+            .write_lcov(&program, "main.tvs", &mut lcov)
+            .unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+        let expected = [
+            "TN:",
+            "SF:main.tvs",
+            "BRDA:3,1,0,1",
+            "BRDA:3,1,1,0",
+            "BRDA:9,5,0,0",
+            "BRDA:9,5,1,1",
+            "BRDA:11,7,0,-",
+            "BRDA:11,7,1,-",
+            "BRF:6",
+            "BRH:2",
+            "DA:2,1",
+            "DA:3,1",
+            "DA:4,0",
+            "DA:6,1",
+            "DA:9,1",
+            "DA:10,1",
+            "DA:11,0",
+            "DA:13,1",
+            "LF:8",
+            "LH:6",
+            "end_of_record",
+        ];
+        assert_eq!(lcov.lines().collect::<Vec<_>>(), expected);
+
+        for record in lcov.lines().filter_map(|line| line.strip_prefix("DA:")) {
+            let (line, hits) = record.split_once(',').unwrap();
+            let (line, hits): (u32, u64) = (line.parse().unwrap(), hits.parse().unwrap());
+            let pcs: Vec<_> = (0..program.len())
+                .filter(|pc| program.line(*pc).unwrap().line == line)
+                .collect();
+            assert!(!pcs.is_empty(), "line {} has no instructions", line);
+            let executed = pcs.iter().map(|pc| coverage.executed(*pc)).max();
+            assert_eq!(executed, Some(hits), "line {}", line);
+        }
+        for record in lcov.lines().filter_map(|line| line.strip_prefix("BRDA:")) {
+            let fields: Vec<usize> = record
+                .split(',')
+                .take(2)
+                .map(|field| field.parse().unwrap())
+                .collect();
+            assert_eq!(program.line(fields[1]).unwrap().line as usize, fields[0]);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::machine::observer::{MachineObserver, NoObserver};
+use crate::machine::value::MachineValue;
+use crate::machine::{Machine, MachineLoopState};
+use crate::op::{OpArg, OpCode};
+use crate::program::SymbolKind;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Result as IoResult, Write};
+
+const LIST_CONTEXT: usize = 4;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Stop {
+    Running,
+    Halted,
+}
+
+pub struct Debugger<'program, W: Write, O = NoObserver> {
+    machine: Machine<'program, O>,
+    output: W,
+    labels: BTreeMap<String, usize>,
+}
+
+impl<'program, W: Write, O: MachineObserver> Debugger<'program, W, O> {
+    pub fn new(machine: Machine<'program, O>, output: W) -> Debugger<'program, W, O> {
+        Self {
+            machine,
+            output,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn define_label(&mut self, name: impl Into<String>, pc: usize) {
+        self.labels.insert(name.into(), pc);
+    }
+
+    pub fn machine(&self) -> &Machine<'program, O> {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> Machine<'program, O> {
+        self.machine
+    }
+
+    pub fn run(&mut self, input: impl BufRead) -> IoResult<()> {
+        write!(self.output, "(tvm) ")?;
+        self.output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if !self.execute(line.trim())? {
+                return Ok(());
+            }
+            write!(self.output, "(tvm) ")?;
+            self.output.flush()?;
+        }
+        writeln!(self.output)
+    }
+
+    pub fn execute(&mut self, line: &str) -> IoResult<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let argument = words.next();
+        match command {
+            "break" | "b" => match argument.and_then(|target| self.resolve(target)) {
+                Some(pc) => {
+                    self.machine.set_breakpoint(pc);
+                    writeln!(self.output, "breakpoint set at #{}", pc)?;
+                }
+                None => writeln!(self.output, "unknown location")?,
+            },
+
+            "delete" | "d" => match argument.and_then(|target| self.resolve(target)) {
+                Some(pc) if self.machine.clear_breakpoint(pc) => {
+                    writeln!(self.output, "breakpoint cleared at #{}", pc)?;
+                }
+                _ => writeln!(self.output, "no breakpoint at that location")?,
+            },
+
+            "step" | "s" => {
+                let count = argument.and_then(|count| count.parse().ok()).unwrap_or(1);
+                for _ in 0..count {
+                    if self.step()? == Stop::Halted {
+                        break;
+                    }
+                }
+                self.show_location()?;
+            }
+
+            "next" | "n" => {
+                let depth = self.machine.calls.len();
+                let call = self
+                    .machine
+                    .code
+                    .get(self.machine.current)
+                    .is_some_and(|op| op.code == OpCode::Call);
+                if self.step()? == Stop::Running && call {
+                    self.resume(|machine| machine.calls.len() <= depth)?;
+                }
+                self.show_location()?;
+            }
+
+            "finish" | "f" => {
+                let depth = self.machine.calls.len();
+                if depth == 0 {
+                    writeln!(self.output, "not inside a call")?;
+                } else if self.step()? == Stop::Running {
+                    self.resume(|machine| machine.calls.len() < depth)?;
+                }
+                self.show_location()?;
+            }
+
+            "continue" | "c" => {
+                if self.step()? == Stop::Running {
+                    self.resume(|_| false)?;
+                }
+                self.show_location()?;
+            }
+
+            "back" | "bs" => {
+                let count = argument.and_then(|count| count.parse().ok()).unwrap_or(1);
+                for _ in 0..count {
+                    if !self.machine.step_back() {
+                        writeln!(self.output, "no earlier history")?;
+                        break;
+                    }
+                }
+                self.show_location()?;
+            }
+
+            "reverse" | "rc" => {
+                match self.machine.run_back() {
+                    Some(pc) => writeln!(self.output, "breakpoint hit at #{}", pc)?,
+                    None => writeln!(self.output, "no earlier history")?,
+                }
+                self.show_location()?;
+            }
+
+            "stack" => {
+                for (i, value) in self.machine.stack.iter().enumerate().rev() {
+                    writeln!(self.output, "  [{}] {}", i, value)?;
+                }
+            }
+
+            "registers" | "regs" => {
+                for (i, value) in self.machine.bank.registers.iter().enumerate() {
+                    writeln!(self.output, "  r{} = {}", i + 1, value)?;
+                }
+            }
+
+            "calls" | "bt" => {
+                writeln!(self.output, "  #{}", self.machine.current)?;
+                for value in self.machine.calls.iter().rev() {
+                    match value {
+                        MachineValue::ReturnAddress(address) => {
+                            writeln!(self.output, "  called from #{}", address.saturating_sub(1))?
+                        }
+                        value => writeln!(self.output, "  {}", value)?,
+                    }
+                }
+            }
+
+            "list" | "l" => {
+                let center = match argument {
+                    Some(target) => match self.resolve(target) {
+                        Some(pc) => pc,
+                        None => {
+                            writeln!(self.output, "unknown location")?;
+                            return Ok(true);
+                        }
+                    },
+                    None => self.machine.current,
+                };
+                self.list(center)?;
+            }
+
+            "push" => match argument.map(str::parse::<OpArg>) {
+                Some(Ok(arg)) => match MachineValue::of(arg, &self.machine.bank) {
+                    Some(value) => self.machine.push(value),
+                    None => writeln!(self.output, "value expected")?,
+                },
+                Some(Err(error)) => writeln!(self.output, "{}", error)?,
+                None => writeln!(self.output, "value expected")?,
+            },
+
+            "reset" => {
+                self.machine.reset();
+                self.show_location()?;
+            }
+
+            "help" | "h" => {
+                writeln!(
+                    self.output,
+                    "commands: break <pc|label>, delete <pc|label>, step [n], next, finish, \
+                     continue, back [n], reverse, stack, registers, calls, list [pc|label], \
+                     push <value>, reset, quit"
+                )?;
+            }
+
+            "quit" | "q" => return Ok(false),
+
+            command => writeln!(self.output, "unknown command `{}`", command)?,
+        }
+        Ok(true)
+    }
+
+    fn resolve(&self, target: &str) -> Option<usize> {
+        let target = target.strip_prefix('#').unwrap_or(target);
+        let program = self.machine.program;
+        target
+            .parse()
+            .ok()
+            .or_else(|| self.labels.get(target).copied())
+            .or_else(|| {
+                program
+                    .symbols()
+                    .iter()
+                    .find(|symbol| symbol.name == target && is_code(symbol.kind))
+                    .map(|symbol| symbol.address)
+            })
+            .or_else(|| program.export(target))
+    }
+
+    fn label_at(&self, pc: usize) -> Option<&str> {
+        let program = self.machine.program;
+        self.labels
+            .iter()
+            .find(|(_, address)| **address == pc)
+            .map(|(name, _)| name.as_str())
+            .or_else(|| {
+                program
+                    .symbol_at(SymbolKind::Function, pc)
+                    .map(|symbol| &*symbol.name)
+            })
+            .or_else(|| {
+                program
+                    .symbol_at(SymbolKind::Label, pc)
+                    .map(|symbol| &*symbol.name)
+            })
+    }
+
+    fn step(&mut self) -> IoResult<Stop> {
+        self.machine.paused = Some(self.machine.current);
+        self.advance()
+    }
+
+    fn advance(&mut self) -> IoResult<Stop> {
+        match self.machine.step_instrumented() {
+            Ok(MachineLoopState::Continue) => Ok(Stop::Running),
+            Ok(MachineLoopState::Break) => {
+                writeln!(self.output, "program exited")?;
+                Ok(Stop::Halted)
+            }
+            Ok(MachineLoopState::Native(id)) => {
+                writeln!(self.output, "native call {} has no host", id)?;
+                Ok(Stop::Halted)
+            }
+            Ok(MachineLoopState::Interrupted) => {
+                writeln!(self.output, "interrupted")?;
+                Ok(Stop::Halted)
+            }
+            Ok(MachineLoopState::Breakpoint(pc)) => {
+                writeln!(self.output, "breakpoint hit at #{}", pc)?;
+                Ok(Stop::Halted)
+            }
+            Ok(MachineLoopState::Watchpoint { id, pc }) => {
+                writeln!(self.output, "watchpoint {} triggered by #{}", id, pc)?;
+                Ok(Stop::Halted)
+            }
+            Err(error) => {
+                writeln!(self.output, "{}", error)?;
+                Ok(Stop::Halted)
+            }
+        }
+    }
+
+    fn resume(&mut self, done: impl Fn(&Machine<'program, O>) -> bool) -> IoResult<()> {
+        loop {
+            if done(&self.machine) {
+                return Ok(());
+            }
+            if self.advance()? == Stop::Halted {
+                return Ok(());
+            }
+        }
+    }
+
+    fn show_location(&mut self) -> IoResult<()> {
+        let pc = self.machine.current;
+        match self.machine.code.get(pc) {
+            Some(op) => writeln!(self.output, "#{}: {}", pc, op),
+            None => writeln!(self.output, "#{}: <end of program>", pc),
+        }
+    }
+
+    fn list(&mut self, center: usize) -> IoResult<()> {
+        let code = self.machine.code;
+        let start = center.saturating_sub(LIST_CONTEXT);
+        let end = (center + LIST_CONTEXT + 1).min(code.len());
+        for (pc, op) in (start..end).filter_map(|pc| Some((pc, code.get(pc)?))) {
+            let marker = if pc == self.machine.current {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = if self.machine.breakpoints.contains_key(&pc) {
+                "*"
+            } else {
+                " "
+            };
+            let label = self
+                .label_at(pc)
+                .map(|name| format!(" <{}>", name))
+                .unwrap_or_default();
+            writeln!(
+                self.output,
+                "{}{} #{}{}: {}",
+                marker, breakpoint, pc, label, op
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn is_code(kind: SymbolKind) -> bool {
+    matches!(kind, SymbolKind::Function | SymbolKind::Label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::machine::Machine;
+    use crate::op::OpArg::Uint64;
+    use crate::op::OpCode::{Exit, Push};
+    use crate::{op, program};
+
+    fn transcript<W: std::io::Write>(debugger: &mut Debugger<'_, W>, commands: &[&str]) {
+        for command in commands {
+            assert!(debugger.execute(command).unwrap());
+        }
+    }
+
+    #[test]
+    fn machine_breakpoints_stop_the_debugger() {
+        let program = program!(
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(2)),
+            op!(Push, Uint64(3)),
+            op!(Exit)
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_breakpoint(2);
+        let mut debugger = Debugger::new(machine, Vec::new());
+        transcript(&mut debugger, &["continue", "continue"]);
+        let output = String::from_utf8(debugger.output.clone()).unwrap();
+        assert_eq!(
+            output,
+            "breakpoint hit at #2\n#2: push 3u64\nprogram exited\n#3: exit\n"
+        );
+    }
+
+    #[test]
+    fn debugger_breakpoints_live_on_the_machine() {
+        let program = program!(op!(Push, Uint64(1)), op!(Push, Uint64(2)), op!(Exit));
+        let mut debugger = Debugger::new(Machine::new(&program), Vec::new());
+        transcript(&mut debugger, &["break 1", "continue"]);
+        assert_eq!(debugger.machine().pc(), 1);
+        transcript(&mut debugger, &["delete 1", "reset", "continue"]);
+        assert_eq!(debugger.machine().pc(), 2);
+        assert!(!debugger.into_machine().clear_breakpoint(1));
+    }
+
+    #[test]
+    fn locations_resolve_from_program_symbols() {
+        let program = crate::program::Program::assemble(
+            "main:\n    push 1\nloop:\n    push 2\n    exit\n.export entry loop\n",
+        )
+        .unwrap();
+        let mut debugger = Debugger::new(Machine::new(&program), Vec::new());
+        transcript(
+            &mut debugger,
+            &["break loop", "delete entry", "break #2", "list main"],
+        );
+        let output = String::from_utf8(debugger.output).unwrap();
+        assert!(output.starts_with("breakpoint set at #1\nbreakpoint cleared at #1\n"));
+        assert!(output.contains("=>  #0 <main>: push 1u64\n    #1 <loop>: push 2u64\n"));
+        assert!(output.contains("  * #2: exit\n"));
+    }
+}
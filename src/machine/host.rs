@@ -0,0 +1,78 @@
+use crate::error::Result;
+use crate::machine::Machine;
+use crate::machine::observer::MachineObserver;
+
+pub trait AsyncHost {
+    fn call<O: MachineObserver>(
+        &mut self,
+        id: u64,
+        machine: &mut Machine<'_, O>,
+    ) -> impl Future<Output = Result<()>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncHost;
+    use crate::error::Result;
+    use crate::machine::observer::MachineObserver;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::{Uint8, Uint64};
+    use crate::op::OpCode::{Exit, Native, Push};
+    use crate::{op, program};
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    struct Yield(bool);
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: std::pin::Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    struct Multiply;
+
+    impl AsyncHost for Multiply {
+        async fn call<O: MachineObserver>(
+            &mut self,
+            id: u64,
+            machine: &mut Machine<'_, O>,
+        ) -> Result<()> {
+            let value = machine.pop()?;
+            Yield(false).await;
+            Yield(false).await;
+            machine.push(MachineValue::Uint64(value.as_u64() * id));
+            Ok(())
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return (output, polls);
+            }
+        }
+    }
+
+    #[test]
+    fn run_async_suspends_on_pending_host_calls() {
+        let program = program!(op!(Push, Uint64(6)), op!(Native, Uint8(7)), op!(Exit));
+        let mut machine = Machine::new(&program);
+        let (result, polls) = block_on(machine.run_async(&mut Multiply));
+        assert_eq!(result, Ok(MachineLoopState::Break));
+        assert_eq!(polls, 3);
+        assert_eq!(machine.stack(), [MachineValue::Uint64(42)]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        Self::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Release);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Acquire)
+    }
+
+    pub fn clear(&self) {
+        self.flag.store(false, Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn take(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) && self.flag.swap(false, Ordering::Acquire)
+    }
+}
+
+impl PartialEq for InterruptHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.flag, &other.flag)
+    }
+}
+
+impl Eq for InterruptHandle {}
+
+#[cfg(test)]
+mod tests {
+    use super::InterruptHandle;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::{Register1, Uint64};
+    use crate::op::OpCode::{Jump, Pop, Push};
+    use crate::{op, program};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn interrupts_stop_a_loop_from_another_thread() {
+        let program = program!(
+            op!(Push, Uint64(7)),
+            start:
+            op!(Push, Uint64(1)),
+            op!(Pop, Register1),
+            op!(Jump, start),
+        );
+        let mut machine = Machine::new(&program);
+        for _ in 0..2 {
+            let handle = machine.interrupt_handle();
+            let interrupter = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                handle.interrupt();
+            });
+            assert_eq!(machine.run(), Ok(MachineLoopState::Interrupted));
+            interrupter.join().unwrap();
+            assert_eq!(machine.pc(), 1);
+            assert_eq!(machine.stack(), [MachineValue::Uint64(7)]);
+            assert_eq!(machine.register(0), Some(MachineValue::Uint64(1)));
+            assert!(!machine.interrupt_handle().is_interrupted());
+        }
+    }
+
+    #[test]
+    fn handles_share_one_flag() {
+        let handle = InterruptHandle::new();
+        let clone = handle.clone();
+        clone.interrupt();
+        assert!(handle.is_interrupted());
+        assert!(handle.take());
+        assert!(!clone.take());
+        handle.interrupt();
+        clone.clear();
+        assert!(!handle.is_interrupted());
+        assert_ne!(handle, InterruptHandle::new());
+    }
+}
@@ -0,0 +1,503 @@
+use crate::error::{MachineError, Result};
+use crate::machine::observer::MachineObserver;
+use crate::machine::value::MachineValue;
+use crate::machine::{Handler, Machine, MachineLoopState, RegisterBank};
+use crate::op::{OpArg, OpCode};
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct JournalOptions {
+    pub max_bytes: usize,
+    pub interval: u64,
+    pub checkpoints: usize,
+}
+
+impl Default for JournalOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 22,
+            interval: 1 << 12,
+            checkpoints: 64,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct State {
+    stack: Vec<MachineValue>,
+    calls: Vec<MachineValue>,
+    bank: RegisterBank,
+    current: usize,
+    handlers: Vec<Handler>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct Undo {
+    current: usize,
+    stack: usize,
+    popped: Vec<MachineValue>,
+    registers: Vec<(usize, MachineValue)>,
+    calls: usize,
+    returned: Vec<MachineValue>,
+    handlers: usize,
+    dropped: Vec<Handler>,
+}
+
+impl Undo {
+    fn size(&self) -> usize {
+        size_of::<Self>()
+            + (self.popped.len() + self.returned.len()) * size_of::<MachineValue>()
+            + self.registers.len() * size_of::<(usize, MachineValue)>()
+            + self.dropped.len() * size_of::<Handler>()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Journal {
+    options: JournalOptions,
+    steps: u64,
+    bytes: usize,
+    entries: VecDeque<Undo>,
+    checkpoints: VecDeque<(u64, State)>,
+}
+
+impl Journal {
+    pub fn new(options: JournalOptions) -> Journal {
+        Self {
+            options,
+            steps: 0,
+            bytes: 0,
+            entries: VecDeque::new(),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps = 0;
+        self.bytes = 0;
+        self.entries.clear();
+        self.checkpoints.clear();
+    }
+
+    fn record(&mut self, undo: Undo) {
+        self.bytes += undo.size();
+        self.entries.push_back(undo);
+        while self.bytes > self.options.max_bytes {
+            let Some(undo) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= undo.size();
+        }
+        self.steps += 1;
+    }
+
+    fn pop(&mut self) -> Option<Undo> {
+        let undo = self.entries.pop_back()?;
+        self.bytes -= undo.size();
+        self.steps -= 1;
+        Some(undo)
+    }
+
+    pub(crate) fn popped(&mut self, index: usize, value: MachineValue) {
+        if let Some(undo) = self.entries.back_mut()
+            && index < undo.stack
+        {
+            undo.stack = index;
+            undo.popped.insert(0, value);
+            self.bytes += size_of::<MachineValue>();
+        }
+    }
+
+    pub(crate) fn overwritten(&mut self, index: usize, value: MachineValue) {
+        if let Some(undo) = self.entries.back_mut() {
+            undo.registers.push((index, value));
+            self.bytes += size_of::<(usize, MachineValue)>();
+        }
+    }
+}
+
+impl<O: MachineObserver> Machine<'_, O> {
+    pub fn enable_journal(&mut self, options: JournalOptions) {
+        self.journal = Some(Box::new(Journal::new(options)));
+    }
+
+    pub fn disable_journal(&mut self) -> Option<Journal> {
+        self.journal.take().map(|journal| *journal)
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_deref()
+    }
+
+    pub fn step_back(&mut self) -> bool {
+        let stepped = self.unstep();
+        if stepped && self.recording.is_some() {
+            self.record_restore();
+        }
+        stepped
+    }
+
+    pub fn run_back(&mut self) -> Option<usize> {
+        let mut hit = None;
+        let mut stepped = false;
+        while self.unstep() {
+            stepped = true;
+            let pc = self.current;
+            let matched = self.breakpoints.get(&pc).is_some_and(|condition| {
+                condition.is_none_or(|condition| condition(&self.state()))
+            });
+            if matched {
+                self.paused = Some(pc);
+                hit = Some(pc);
+                break;
+            }
+        }
+        if stepped && self.recording.is_some() {
+            self.record_restore();
+        }
+        hit
+    }
+
+    fn unstep(&mut self) -> bool {
+        let Some(undo) = self.journal.as_mut().and_then(|journal| journal.pop()) else {
+            return false;
+        };
+        self.undo(undo);
+        true
+    }
+
+    pub fn rewind(&mut self, target: u64) -> Result<u64> {
+        let Some(journal) = &mut self.journal else {
+            return Err(MachineError::RewindOutOfRange(target));
+        };
+        let rewound = journal.steps > target;
+        let earliest = journal.steps - journal.entries.len() as u64;
+        if target < earliest {
+            let Some(index) = journal
+                .checkpoints
+                .iter()
+                .rposition(|(steps, _)| *steps <= target)
+            else {
+                return Err(MachineError::RewindOutOfRange(target));
+            };
+            journal.checkpoints.truncate(index + 1);
+            let (steps, state) = journal.checkpoints[index].clone();
+            journal.steps = steps;
+            journal.bytes = 0;
+            journal.entries.clear();
+            self.apply(state);
+        }
+
+        while self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.steps > target)
+        {
+            self.unstep();
+        }
+        if rewound && self.recording.is_some() {
+            self.record_restore();
+        }
+        while let Some(journal) = &self.journal {
+            if journal.steps >= target {
+                return Ok(journal.steps);
+            }
+            if self.step()? != MachineLoopState::Continue {
+                break;
+            }
+        }
+        Ok(self.journal.as_ref().map_or(0, |journal| journal.steps))
+    }
+
+    pub(crate) fn step_journaled(&mut self) -> Result<MachineLoopState> {
+        let undo = self.capture();
+        let checkpoint = self.journal.as_ref().is_some_and(|journal| {
+            journal.options.interval > 0
+                && journal.options.checkpoints > 0
+                && journal.steps.is_multiple_of(journal.options.interval)
+        });
+        if checkpoint {
+            let state = self.copy_state();
+            if let Some(journal) = &mut self.journal {
+                while journal
+                    .checkpoints
+                    .back()
+                    .is_some_and(|(steps, _)| *steps >= journal.steps)
+                {
+                    journal.checkpoints.pop_back();
+                }
+                if journal.checkpoints.len() >= journal.options.checkpoints {
+                    journal.checkpoints.pop_front();
+                }
+                journal.checkpoints.push_back((journal.steps, state));
+            }
+        }
+        let result = self.advance();
+        if let Some(journal) = &mut self.journal {
+            journal.record(undo);
+        }
+        result
+    }
+
+    fn capture(&self) -> Undo {
+        let op = self.code.get(self.current);
+        let code = op.map(|op| op.code);
+        let consumed = match code {
+            Some(OpCode::Pop | OpCode::JumpIfZero | OpCode::Throw) => 1,
+            Some(
+                OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::JumpIfEqual,
+            ) => 2,
+            _ => 0,
+        };
+        let mut stack = self.stack.len().saturating_sub(consumed);
+        let mut calls = self.calls.len();
+        let mut handlers = self.handlers.len();
+        match code {
+            Some(OpCode::TryEnd) => handlers = handlers.saturating_sub(1),
+            Some(OpCode::Return) => {
+                calls = calls.saturating_sub(1);
+                handlers = self
+                    .handlers
+                    .iter()
+                    .rposition(|handler| handler.calls <= calls)
+                    .map_or(0, |index| index + 1);
+            }
+            _ => {}
+        }
+        let throws = code == Some(OpCode::Throw) || self.catch_faults;
+        if let Some(handler) = self.handlers.last().filter(|_| throws) {
+            stack = stack.min(handler.stack);
+            calls = calls.min(handler.calls);
+            handlers = handlers.min(self.handlers.len() - 1);
+        }
+
+        let registers = op
+            .filter(|op| op.code == OpCode::Pop)
+            .and_then(|op| Some((register_index(op.arg)?, self.bank.load(op.arg)?)))
+            .into_iter()
+            .collect();
+        Undo {
+            current: self.current,
+            stack,
+            popped: self.stack[stack..].to_vec(),
+            registers,
+            calls,
+            returned: self.calls[calls..].to_vec(),
+            handlers,
+            dropped: self.handlers[handlers..].to_vec(),
+        }
+    }
+
+    fn copy_state(&self) -> State {
+        State {
+            stack: self.stack.clone(),
+            calls: self.calls.clone(),
+            bank: self.bank,
+            current: self.current,
+            handlers: self.handlers.clone(),
+        }
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        self.stack.truncate(undo.stack);
+        self.stack.extend(undo.popped);
+        self.calls.truncate(undo.calls);
+        self.calls.extend(undo.returned);
+        self.handlers.truncate(undo.handlers);
+        self.handlers.extend(undo.dropped);
+        for (index, value) in undo.registers.into_iter().rev() {
+            let _ = self.bank.set(index, value);
+        }
+        self.current = undo.current;
+        self.paused = None;
+        self.pending = None;
+    }
+
+    fn apply(&mut self, state: State) {
+        self.stack = state.stack;
+        self.calls = state.calls;
+        self.bank = state.bank;
+        self.current = state.current;
+        self.handlers = state.handlers;
+        self.paused = None;
+        self.pending = None;
+    }
+}
+
+fn register_index(arg: OpArg) -> Option<usize> {
+    match arg {
+        OpArg::Register1 => Some(0),
+        OpArg::Register2 => Some(1),
+        OpArg::Register3 => Some(2),
+        OpArg::Register4 => Some(3),
+        OpArg::Register5 => Some(4),
+        OpArg::Register6 => Some(5),
+        OpArg::Register7 => Some(6),
+        OpArg::Register8 => Some(7),
+        OpArg::Register9 => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JournalOptions, Undo};
+    use crate::error::MachineError;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::{Register1, Uint64};
+    use crate::op::OpCode::{
+        Call, Divide, Exit, Jump, Native, Pop, Push, Return, Throw, TryBegin, TryEnd,
+    };
+    use crate::{op, program};
+    use std::mem::size_of;
+
+    type Observed = (
+        Vec<MachineValue>,
+        Vec<MachineValue>,
+        Vec<MachineValue>,
+        usize,
+    );
+
+    fn observe(machine: &Machine<'_>) -> Observed {
+        (
+            machine.stack.clone(),
+            machine.calls.clone(),
+            machine.bank.registers().to_vec(),
+            machine.current,
+        )
+    }
+
+    #[test]
+    fn caught_faults_and_throws_undo_from_deltas() {
+        let program = program!(
+            op!(TryBegin, outer),
+            op!(Push, Uint64(5)),
+            op!(Call, function),
+            op!(Exit),
+            outer:
+            op!(Pop, Register1),
+            op!(TryBegin, inner),
+            op!(Push, Uint64(9)),
+            op!(Throw),
+            inner:
+            op!(Exit),
+            function:
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(0)),
+            op!(Divide),
+            op!(Return),
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_catch_faults(true);
+        machine.enable_journal(JournalOptions::default());
+        let mut history = vec![observe(&machine)];
+        loop {
+            let state = machine.step();
+            history.push(observe(&machine));
+            if state != Ok(MachineLoopState::Continue) {
+                break;
+            }
+        }
+        assert_eq!(machine.stack, [MachineValue::Uint64(9)]);
+
+        let journal = machine.journal().unwrap();
+        assert_eq!(journal.len(), history.len() - 1);
+        assert!(journal.entries.iter().all(|undo| undo.popped.len() <= 3));
+        while machine.step_back() {
+            history.pop();
+            assert_eq!(observe(&machine), *history.last().unwrap());
+        }
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn host_changes_after_a_native_call_are_undone() {
+        let program = program!(op!(Push, Uint64(1)), op!(Native, Uint64(0)), op!(Exit));
+        let mut machine = Machine::new(&program);
+        machine.enable_journal(JournalOptions::default());
+        machine.step().unwrap();
+        let before = observe(&machine);
+        assert_eq!(machine.step(), Ok(MachineLoopState::Native(0)));
+        let value = machine.pop().unwrap();
+        machine.push(MachineValue::Uint64(value.as_u64() * 7));
+        machine.set_register(2, value).unwrap();
+        assert!(machine.step_back());
+        assert_eq!(observe(&machine), before);
+    }
+
+    #[test]
+    fn journal_is_bounded_by_bytes() {
+        let program = program!(start: op!(Push, Uint64(1)), op!(Pop, Register1), op!(Jump, start));
+        let max_bytes = 64 * size_of::<Undo>();
+        let mut machine = Machine::new(&program);
+        machine.enable_journal(JournalOptions {
+            max_bytes,
+            ..JournalOptions::default()
+        });
+        for _ in 0..1000 {
+            machine.step().unwrap();
+        }
+        let journal = machine.journal().unwrap();
+        assert!(journal.bytes() <= max_bytes);
+        assert!(journal.len() > 16 && journal.len() < 64);
+        assert_eq!(journal.steps(), 1000);
+    }
+
+    #[test]
+    fn rewind_keeps_checkpoints_when_the_target_is_out_of_range() {
+        let program = program!(start: op!(Push, Uint64(1)), op!(Pop, Register1), op!(Jump, start));
+        let mut machine = Machine::new(&program);
+        machine.enable_journal(JournalOptions {
+            max_bytes: 2 * size_of::<Undo>() + 64,
+            interval: 4,
+            checkpoints: 2,
+        });
+        for _ in 0..26 {
+            machine.step().unwrap();
+        }
+        let checkpoints = machine.journal().unwrap().checkpoints.clone();
+        assert_eq!(machine.rewind(3), Err(MachineError::RewindOutOfRange(3)));
+        assert_eq!(machine.journal().unwrap().steps(), 26);
+        assert_eq!(machine.journal().unwrap().checkpoints, checkpoints);
+
+        assert_eq!(machine.rewind(21), Ok(21));
+        assert_eq!(machine.pc(), 0);
+        assert_eq!(machine.rewind(26), Ok(26));
+        assert_eq!(machine.journal().unwrap().checkpoints, checkpoints);
+    }
+
+    #[test]
+    fn handlers_dropped_by_try_end_are_restored() {
+        let program = program!(op!(TryBegin, done), op!(TryEnd), done: op!(Exit));
+        let mut machine = Machine::new(&program);
+        machine.enable_journal(JournalOptions::default());
+        machine.step().unwrap();
+        let handlers = machine.handlers.clone();
+        machine.step().unwrap();
+        assert!(machine.handlers.is_empty());
+        assert!(machine.step_back());
+        assert_eq!(machine.handlers, handlers);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::MachineError;
+use crate::machine::MachineState;
+use crate::op::Op;
+
+pub trait MachineObserver {
+    #[inline(always)]
+    fn before_step(&mut self, _pc: usize, _op: &Op, _state: &MachineState<'_>) {}
+
+    #[inline(always)]
+    fn after_step(&mut self, _pc: usize, _op: &Op, _state: &MachineState<'_>) {}
+
+    #[inline(always)]
+    fn on_call(&mut self, _from: usize, _to: usize) {}
+
+    #[inline(always)]
+    fn on_return(&mut self, _from: usize, _to: usize) {}
+
+    #[inline(always)]
+    fn on_branch(&mut self, _pc: usize, _taken: bool) {}
+
+    #[inline(always)]
+    fn on_error(&mut self, _pc: usize, _error: &MachineError) {}
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl MachineObserver for NoObserver {}
+
+impl<O: MachineObserver> MachineObserver for &mut O {
+    fn before_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        (**self).before_step(pc, op, state);
+    }
+
+    fn after_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        (**self).after_step(pc, op, state);
+    }
+
+    fn on_call(&mut self, from: usize, to: usize) {
+        (**self).on_call(from, to);
+    }
+
+    fn on_return(&mut self, from: usize, to: usize) {
+        (**self).on_return(from, to);
+    }
+
+    fn on_branch(&mut self, pc: usize, taken: bool) {
+        (**self).on_branch(pc, taken);
+    }
+
+    fn on_error(&mut self, pc: usize, error: &MachineError) {
+        (**self).on_error(pc, error);
+    }
+}
+
+impl<A: MachineObserver, B: MachineObserver> MachineObserver for (A, B) {
+    fn before_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        self.0.before_step(pc, op, state);
+        self.1.before_step(pc, op, state);
+    }
+
+    fn after_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        self.0.after_step(pc, op, state);
+        self.1.after_step(pc, op, state);
+    }
+
+    fn on_call(&mut self, from: usize, to: usize) {
+        self.0.on_call(from, to);
+        self.1.on_call(from, to);
+    }
+
+    fn on_return(&mut self, from: usize, to: usize) {
+        self.0.on_return(from, to);
+        self.1.on_return(from, to);
+    }
+
+    fn on_branch(&mut self, pc: usize, taken: bool) {
+        self.0.on_branch(pc, taken);
+        self.1.on_branch(pc, taken);
+    }
+
+    fn on_error(&mut self, pc: usize, error: &MachineError) {
+        self.0.on_error(pc, error);
+        self.1.on_error(pc, error);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::machine::MachineState;
+use crate::machine::observer::MachineObserver;
+use crate::op::{Op, OpCode};
+use crate::program::{Program, SymbolKind};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Result as IoResult, Write};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct FunctionProfile {
+    pub entry: usize,
+    pub calls: u64,
+    pub exclusive: u64,
+    pub inclusive: u64,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Profiler {
+    instructions: Vec<u64>,
+    opcodes: HashMap<OpCode, u64>,
+    calls: BTreeMap<usize, u64>,
+    frames: Vec<usize>,
+    stacks: BTreeMap<Vec<usize>, u64>,
+    names: BTreeMap<usize, String>,
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Self {
+            instructions: Vec::new(),
+            opcodes: HashMap::new(),
+            calls: BTreeMap::new(),
+            frames: vec![0],
+            stacks: BTreeMap::new(),
+            names: BTreeMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn for_program(program: &Program) -> Profiler {
+        let mut profiler = Self::new();
+        for symbol in program.symbols() {
+            if symbol.kind == SymbolKind::Function {
+                profiler.define_function(symbol.name.clone(), symbol.address);
+            }
+        }
+        profiler
+    }
+
+    pub fn define_function(&mut self, name: impl Into<String>, entry: usize) {
+        self.names.insert(entry, name.into());
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn instructions(&self) -> &[u64] {
+        &self.instructions
+    }
+
+    pub fn opcode(&self, code: OpCode) -> u64 {
+        self.opcodes.get(&code).copied().unwrap_or(0)
+    }
+
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: BTreeMap<usize, FunctionProfile> = BTreeMap::new();
+        for (stack, count) in &self.stacks {
+            let mut seen = BTreeSet::new();
+            for entry in stack {
+                if seen.insert(*entry) {
+                    functions.entry(*entry).or_default().inclusive += count;
+                }
+            }
+            if let Some(entry) = stack.last() {
+                functions.entry(*entry).or_default().exclusive += count;
+            }
+        }
+        for (entry, calls) in &self.calls {
+            functions.entry(*entry).or_default().calls = *calls;
+        }
+        functions
+            .into_iter()
+            .map(|(entry, function)| FunctionProfile { entry, ..function })
+            .collect()
+    }
+
+    pub fn name(&self, entry: usize) -> String {
+        match self.names.get(&entry) {
+            Some(name) => name.clone(),
+            None if entry == 0 => "main".to_string(),
+            None => format!("fn@{}", entry),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            names: std::mem::take(&mut self.names),
+            ..Self::new()
+        };
+    }
+
+    pub fn write_report(
+        &self,
+        program: &Program,
+        writer: &mut impl Write,
+        limit: usize,
+    ) -> IoResult<()> {
+        writeln!(writer, "{} instructions executed", self.total)?;
+
+        writeln!(writer)?;
+        writeln!(writer, "hot instructions:")?;
+        let mut hot: Vec<(usize, u64)> = self
+            .instructions
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (pc, count) in hot.into_iter().take(limit) {
+            let op = program
+                .get(pc)
+                .as_ref()
+                .map(Op::to_string)
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "  {:>12} {:>6.2}%  #{}: {}",
+                count,
+                self.percent(count),
+                pc,
+                op
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "opcodes:")?;
+        let mut opcodes: Vec<(OpCode, u64)> = self
+            .opcodes
+            .iter()
+            .map(|(code, count)| (*code, *count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+        for (code, count) in opcodes {
+            writeln!(
+                writer,
+                "  {:>12} {:>6.2}%  {}",
+                count,
+                self.percent(count),
+                code
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "functions:")?;
+        writeln!(
+            writer,
+            "  {:>12} {:>12} {:>12}  name",
+            "self", "total", "calls"
+        )?;
+        let mut functions = self.functions();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.entry.cmp(&b.entry)));
+        for function in functions.into_iter().take(limit) {
+            writeln!(
+                writer,
+                "  {:>12} {:>12} {:>12}  {}",
+                function.exclusive,
+                function.inclusive,
+                function.calls,
+                self.name(function.entry)
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_folded(&self, writer: &mut impl Write) -> IoResult<()> {
+        for (stack, count) in &self.stacks {
+            let names: Vec<String> = stack.iter().map(|entry| self.name(*entry)).collect();
+            writeln!(writer, "{} {}", names.join(";"), count)?;
+        }
+        Ok(())
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+impl MachineObserver for Profiler {
+    fn before_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        self.frames.truncate(state.calls.len() + 1);
+        if pc >= self.instructions.len() {
+            self.instructions.resize(pc + 1, 0);
+        }
+        self.instructions[pc] += 1;
+        *self.opcodes.entry(op.code).or_insert(0) += 1;
+        match self.stacks.get_mut(self.frames.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.frames.clone(), 1);
+            }
+        }
+        self.total += 1;
+    }
+
+    fn on_call(&mut self, _from: usize, to: usize) {
+        self.frames.push(to);
+        *self.calls.entry(to).or_insert(0) += 1;
+    }
+
+    fn on_return(&mut self, _from: usize, _to: usize) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FunctionProfile, Profiler};
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::Uint64;
+    use crate::op::OpCode::{Add, Call, Exit, Push, Return};
+    use crate::program::Program;
+    use crate::program::builder::ProgramBuilder;
+
+    fn program() -> Program {
+        let mut builder = ProgramBuilder::new();
+        let main = builder.function("main", 0);
+        let double = builder.function("double", 1);
+        let inc = builder.function("inc", 1);
+        builder
+            .bind(main)
+            .push(Uint64(2))
+            .call(double)
+            .call(double)
+            .emit(Exit);
+        builder.bind(double).call(inc).emit(Return);
+        builder.bind(inc).push(Uint64(1)).emit(Add).emit(Return);
+        builder.build().unwrap()
+    }
+
+    fn profile(program: &Program) -> Profiler {
+        let mut machine = Machine::with_observer(program, Profiler::for_program(program));
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        machine.into_observer()
+    }
+
+    #[test]
+    fn counts_instructions_and_opcodes() {
+        let profiler = profile(&program());
+        assert_eq!(profiler.total(), 14);
+        assert_eq!(profiler.instructions(), [1, 1, 1, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(profiler.opcode(Call), 4);
+        assert_eq!(profiler.opcode(Return), 4);
+        assert_eq!(profiler.opcode(Push), 3);
+        assert_eq!(profiler.opcode(Add), 2);
+        assert_eq!(profiler.opcode(Exit), 1);
+    }
+
+    #[test]
+    fn attributes_time_to_functions() {
+        let profiler = profile(&program());
+        let function = |entry, calls, exclusive, inclusive| FunctionProfile {
+            entry,
+            calls,
+            exclusive,
+            inclusive,
+        };
+        assert_eq!(
+            profiler.functions(),
+            [
+                function(0, 0, 4, 14),
+                function(4, 2, 4, 10),
+                function(6, 2, 6, 6)
+            ]
+        );
+        assert_eq!(profiler.name(4), "double");
+        assert_eq!(Profiler::new().name(4), "fn@4");
+    }
+
+    #[test]
+    fn writes_folded_stacks_and_reports() {
+        let program = program();
+        let profiler = profile(&program);
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 4\nmain;double 4\nmain;double;inc 6\n"
+        );
+
+        let mut report = Vec::new();
+        profiler.write_report(&program, &mut report, 2).unwrap();
+        let expected = [
+            "14 instructions executed",
+            "",
+            "hot instructions:",
+            "             2  14.29%  #4: call @6",
+            "             2  14.29%  #5: ret",
+            "",
+            "opcodes:",
+            "             4  28.57%  call",
+            "             4  28.57%  ret",
+            "             3  21.43%  push",
+            "             2  14.29%  add",
+            "             1   7.14%  exit",
+            "",
+            "functions:",
+            "          self        total        calls  name",
+            "             6            6            2  inc",
+            "             4           14            0  main",
+        ];
+        assert_eq!(
+            String::from_utf8(report)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
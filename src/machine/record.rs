@@ -0,0 +1,404 @@
+use crate::error::{MachineError, Result};
+use crate::machine::observer::MachineObserver;
+use crate::machine::snapshot::Reader;
+use crate::machine::value::MachineValue;
+use crate::machine::{Machine, MachineLoopState};
+use crate::program::Program;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+const MAGIC: &[u8; 4] = b"TVMR";
+const VERSION: u16 = 2;
+
+const EVENT_PUSH: u8 = 0;
+const EVENT_POP: u8 = 1;
+const EVENT_RESET: u8 = 2;
+const EVENT_RESTORE: u8 = 3;
+const EVENT_STEP: u8 = 4;
+const EVENT_SET_PC: u8 = 5;
+const EVENT_SET_REGISTER: u8 = 6;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Outcome {
+    State(MachineLoopState),
+    Error(u32),
+}
+
+impl Outcome {
+    pub fn of(result: &Result<MachineLoopState>) -> Outcome {
+        match result {
+            Ok(state) => Outcome::State(*state),
+            Err(error) => Outcome::Error(error.code()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Event {
+    Push(MachineValue),
+    Pop(Option<MachineValue>),
+    Reset,
+    Restore(Vec<u8>),
+    Step { outcome: Outcome, digest: u64 },
+    SetPc(usize),
+    SetRegister(usize, MachineValue),
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Event::Push(value) => write!(f, "push {}", value),
+            Event::Pop(Some(value)) => write!(f, "pop {}", value),
+            Event::Pop(None) => write!(f, "pop from empty stack"),
+            Event::Reset => write!(f, "reset"),
+            Event::Restore(bytes) => write!(f, "restore {} bytes", bytes.len()),
+            Event::Step {
+                outcome: Outcome::State(state),
+                digest,
+            } => write!(f, "step {:?} (state {:016x})", state, digest),
+            Event::Step {
+                outcome: Outcome::Error(code),
+                digest,
+            } => write!(f, "step error {} (state {:016x})", code, digest),
+            Event::SetPc(pc) => write!(f, "set pc #{}", pc),
+            Event::SetRegister(index, value) => write!(f, "set r{} {}", index + 1, value),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Divergence {
+    pub event: usize,
+    pub step: u64,
+    pub expected: Event,
+    pub actual: Event,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "replay diverged at step {} (event {}): expected {}, got {}",
+            self.step, self.event, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Recording {
+    fingerprint: u64,
+    events: Vec<Event>,
+}
+
+impl Recording {
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        buffer.extend_from_slice(&self.fingerprint.to_le_bytes());
+        let mut value = [0; MachineValue::encoded_len()];
+        for event in &self.events {
+            match event {
+                Event::Push(pushed) => {
+                    buffer.push(EVENT_PUSH);
+                    pushed.encode(&mut value);
+                    buffer.extend_from_slice(&value);
+                }
+                Event::Pop(popped) => {
+                    buffer.push(EVENT_POP);
+                    buffer.push(popped.is_some() as u8);
+                    popped.unwrap_or_default().encode(&mut value);
+                    buffer.extend_from_slice(&value);
+                }
+                Event::Reset => buffer.push(EVENT_RESET),
+                Event::Restore(bytes) => {
+                    buffer.push(EVENT_RESTORE);
+                    buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                    buffer.extend_from_slice(bytes);
+                }
+                Event::Step { outcome, digest } => {
+                    buffer.push(EVENT_STEP);
+                    let (tag, first, second) = match *outcome {
+                        Outcome::State(MachineLoopState::Continue) => (0, 0, 0),
+                        Outcome::State(MachineLoopState::Break) => (1, 0, 0),
+                        Outcome::State(MachineLoopState::Native(id)) => (2, id, 0),
+                        Outcome::State(MachineLoopState::Interrupted) => (3, 0, 0),
+                        Outcome::State(MachineLoopState::Breakpoint(pc)) => (4, pc as u64, 0),
+                        Outcome::State(MachineLoopState::Watchpoint { id, pc }) => {
+                            (5, id as u64, pc as u64)
+                        }
+                        Outcome::Error(code) => (6, code as u64, 0),
+                    };
+                    buffer.push(tag);
+                    buffer.extend_from_slice(&first.to_le_bytes());
+                    buffer.extend_from_slice(&second.to_le_bytes());
+                    buffer.extend_from_slice(&digest.to_le_bytes());
+                }
+                Event::SetPc(pc) => {
+                    buffer.push(EVENT_SET_PC);
+                    buffer.extend_from_slice(&(*pc as u64).to_le_bytes());
+                }
+                Event::SetRegister(index, stored) => {
+                    buffer.push(EVENT_SET_REGISTER);
+                    buffer.push(*index as u8);
+                    stored.encode(&mut value);
+                    buffer.extend_from_slice(&value);
+                }
+            }
+        }
+        buffer
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Recording> {
+        let mut reader = Reader::new(buffer, MachineError::RecordingInvalid);
+        if reader.take(MAGIC.len())? != MAGIC || reader.u16()? != VERSION {
+            return Err(MachineError::RecordingInvalid);
+        }
+        let fingerprint = reader.u64()?;
+        let mut events = Vec::new();
+        while !reader.buffer.is_empty() {
+            let event = match reader.u8()? {
+                EVENT_PUSH => Event::Push(value(&mut reader)?),
+                EVENT_POP => {
+                    let present = reader.u8()? != 0;
+                    let popped = value(&mut reader)?;
+                    Event::Pop(present.then_some(popped))
+                }
+                EVENT_RESET => Event::Reset,
+                EVENT_RESTORE => {
+                    let length = reader.u32()? as usize;
+                    Event::Restore(reader.take(length)?.to_vec())
+                }
+                EVENT_STEP => {
+                    let tag = reader.u8()?;
+                    let first = reader.u64()?;
+                    let second = reader.u64()?;
+                    let outcome = match tag {
+                        0 => Outcome::State(MachineLoopState::Continue),
+                        1 => Outcome::State(MachineLoopState::Break),
+                        2 => Outcome::State(MachineLoopState::Native(first)),
+                        3 => Outcome::State(MachineLoopState::Interrupted),
+                        4 => Outcome::State(MachineLoopState::Breakpoint(first as usize)),
+                        5 => Outcome::State(MachineLoopState::Watchpoint {
+                            id: first as usize,
+                            pc: second as usize,
+                        }),
+                        6 => Outcome::Error(first as u32),
+                        _ => return Err(MachineError::RecordingInvalid),
+                    };
+                    Event::Step {
+                        outcome,
+                        digest: reader.u64()?,
+                    }
+                }
+                EVENT_SET_PC => Event::SetPc(reader.usize()?),
+                EVENT_SET_REGISTER => {
+                    let index = reader.u8()? as usize;
+                    Event::SetRegister(index, value(&mut reader)?)
+                }
+                _ => return Err(MachineError::RecordingInvalid),
+            };
+            events.push(event);
+        }
+        Ok(Recording {
+            fingerprint,
+            events,
+        })
+    }
+}
+
+fn value(reader: &mut Reader<'_>) -> Result<MachineValue> {
+    MachineValue::decode(reader.take(MachineValue::encoded_len())?)
+        .ok_or(MachineError::RecordingInvalid)
+}
+
+impl<'program> Machine<'program> {
+    pub fn replay(program: &'program Program, recording: &Recording) -> Result<Machine<'program>> {
+        if recording.fingerprint != program.fingerprint() {
+            return Err(MachineError::RecordingMismatch);
+        }
+
+        let mut machine = Machine::new(program);
+        let mut steps = 0;
+        for (index, event) in recording.events.iter().enumerate() {
+            let actual = match event {
+                Event::Push(value) => {
+                    machine.push(*value);
+                    continue;
+                }
+                Event::Pop(_) => Event::Pop(machine.pop().ok()),
+                Event::Reset => {
+                    machine.reset();
+                    continue;
+                }
+                Event::Restore(bytes) => {
+                    machine.load_snapshot(bytes)?;
+                    continue;
+                }
+                Event::SetPc(pc) => {
+                    machine.set_pc(*pc)?;
+                    continue;
+                }
+                Event::SetRegister(index, value) => {
+                    machine.set_register(*index, *value)?;
+                    continue;
+                }
+                Event::Step { outcome, .. } => {
+                    if *outcome == Outcome::State(MachineLoopState::Interrupted) {
+                        machine.interrupt.interrupt();
+                    }
+                    let result = machine.step();
+                    steps += 1;
+                    Event::Step {
+                        outcome: Outcome::of(&result),
+                        digest: machine.digest(),
+                    }
+                }
+            };
+            if actual != *event {
+                return Err(MachineError::Divergence(Box::new(Divergence {
+                    event: index,
+                    step: steps,
+                    expected: event.clone(),
+                    actual,
+                })));
+            }
+        }
+        Ok(machine)
+    }
+}
+
+impl<O: MachineObserver> Machine<'_, O> {
+    pub fn start_recording(&mut self) {
+        let snapshot = self.snapshot();
+        self.recording = Some(Box::new(Recording {
+            fingerprint: self.code.fingerprint(),
+            events: vec![Event::Restore(snapshot)],
+        }));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take().map(|recording| *recording)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    #[cold]
+    pub(crate) fn record(&mut self, event: impl FnOnce(&Self) -> Event) {
+        let event = event(self);
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(event);
+        }
+    }
+
+    pub(crate) fn record_restore(&mut self) {
+        self.record(|machine| Event::Restore(machine.snapshot()));
+    }
+
+    pub(crate) fn digest(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut mix = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        let mut value = [0; MachineValue::encoded_len()];
+        mix(&(self.current as u64).to_le_bytes());
+        mix(&(self.stack.len() as u64).to_le_bytes());
+        mix(&(self.calls.len() as u64).to_le_bytes());
+        mix(&(self.handlers.len() as u64).to_le_bytes());
+        for pushed in self
+            .stack
+            .iter()
+            .chain(&self.calls)
+            .chain(&self.bank.registers)
+        {
+            pushed.encode(&mut value);
+            mix(&value);
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Recording};
+    use crate::error::MachineError;
+    use crate::machine::journal::JournalOptions;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::{Register1, Uint64};
+    use crate::op::OpCode::{Add, Exit, Pop, Push};
+    use crate::{op, program};
+
+    #[test]
+    fn digest_covers_the_whole_stack() {
+        let program = program!(op!(Exit));
+        let mut first = Machine::new(&program);
+        let mut second = Machine::new(&program);
+        first.push(MachineValue::Uint64(1));
+        second.push(MachineValue::Uint64(3));
+        for machine in [&mut first, &mut second] {
+            machine.push(MachineValue::Uint64(2));
+        }
+        assert_ne!(first.digest(), second.digest());
+    }
+
+    #[test]
+    fn recordings_round_trip_and_replay() {
+        let program = program!(
+            op!(Push, Uint64(2)),
+            op!(Add),
+            op!(Pop, Register1),
+            op!(Exit)
+        );
+        let mut machine = Machine::new(&program);
+        machine.start_recording();
+        machine.push(MachineValue::Uint64(40));
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        let recording = machine.stop_recording().unwrap();
+        let decoded = Recording::decode(&recording.encode()).unwrap();
+        assert_eq!(decoded, recording);
+
+        let replayed = Machine::replay(&program, &decoded).unwrap();
+        assert_eq!(replayed.register(0), Some(MachineValue::Uint64(42)));
+
+        let mut tampered = recording.clone();
+        tampered.events[1] = Event::Push(MachineValue::Uint64(41));
+        let Err(MachineError::Divergence(divergence)) = Machine::replay(&program, &tampered) else {
+            panic!("expected a divergence");
+        };
+        assert_eq!(divergence.step, 1);
+    }
+
+    #[test]
+    fn stepping_back_while_recording_replays() {
+        let program = program!(
+            op!(Push, Uint64(1)),
+            op!(Push, Uint64(2)),
+            op!(Push, Uint64(3)),
+            op!(Exit)
+        );
+        let mut machine = Machine::new(&program);
+        machine.enable_journal(JournalOptions::default());
+        machine.start_recording();
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert!(machine.step_back());
+        assert_eq!(machine.rewind(1), Ok(1));
+        machine.push(MachineValue::Uint64(9));
+        machine.step().unwrap();
+        let recording = machine.stop_recording().unwrap();
+
+        let replayed = Machine::replay(&program, &recording).unwrap();
+        assert_eq!(replayed.stack(), machine.stack());
+        assert_eq!(replayed.pc(), machine.pc());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::error::{MachineError, Result};
+use crate::machine::observer::MachineObserver;
+use crate::machine::record::Event;
+use crate::machine::value::MachineValue;
+use crate::machine::{Handler, Machine, RegisterBank};
+use crate::program::Program;
+
+const MAGIC: &[u8; 4] = b"TVMS";
+const VERSION: u16 = 1;
+
+const SECTION_PROGRAM: u8 = 1;
+const SECTION_CURRENT: u8 = 2;
+const SECTION_STACK: u8 = 3;
+const SECTION_CALLS: u8 = 4;
+const SECTION_REGISTERS: u8 = 5;
+const SECTION_HANDLERS: u8 = 6;
+const SECTION_FLAGS: u8 = 7;
+const SECTIONS: u8 = u8::MAX << SECTION_PROGRAM;
+
+impl<'program> Machine<'program> {
+    pub fn restore(program: &'program Program, buffer: &[u8]) -> Result<Machine<'program>> {
+        let mut machine = Machine::new(program);
+        machine.load_snapshot(buffer)?;
+        Ok(machine)
+    }
+}
+
+impl<O: MachineObserver> Machine<'_, O> {
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+
+        section(&mut buffer, SECTION_PROGRAM, |buffer| {
+            buffer.extend_from_slice(&self.code.fingerprint().to_le_bytes());
+        });
+        section(&mut buffer, SECTION_CURRENT, |buffer| {
+            buffer.extend_from_slice(&(self.current as u64).to_le_bytes());
+        });
+        section(&mut buffer, SECTION_STACK, |buffer| {
+            values(buffer, &self.stack)
+        });
+        section(&mut buffer, SECTION_CALLS, |buffer| {
+            values(buffer, &self.calls)
+        });
+        section(&mut buffer, SECTION_REGISTERS, |buffer| {
+            values(buffer, &self.bank.registers)
+        });
+        section(&mut buffer, SECTION_HANDLERS, |buffer| {
+            for handler in &self.handlers {
+                buffer.extend_from_slice(&(handler.target as u64).to_le_bytes());
+                buffer.extend_from_slice(&(handler.stack as u64).to_le_bytes());
+                buffer.extend_from_slice(&(handler.calls as u64).to_le_bytes());
+            }
+        });
+        section(&mut buffer, SECTION_FLAGS, |buffer| {
+            buffer.push(self.catch_faults as u8);
+        });
+        buffer
+    }
+
+    pub fn load_snapshot(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(buffer, MachineError::SnapshotInvalid);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(MachineError::SnapshotInvalid);
+        }
+        if reader.u16()? != VERSION {
+            return Err(MachineError::SnapshotInvalid);
+        }
+
+        let mut fingerprint = None;
+        let mut current = 0;
+        let mut stack = Vec::new();
+        let mut calls = Vec::new();
+        let mut bank = RegisterBank::new();
+        let mut handlers = Vec::new();
+        let mut catch_faults = false;
+        let mut seen = 0u8;
+        while !reader.buffer.is_empty() {
+            let tag = reader.u8()?;
+            if (SECTION_PROGRAM..=SECTION_FLAGS).contains(&tag) {
+                seen |= 1 << tag;
+            }
+            let length = reader.u32()? as usize;
+            let mut payload = Reader::new(reader.take(length)?, MachineError::SnapshotInvalid);
+            match tag {
+                SECTION_PROGRAM => fingerprint = Some(payload.u64()?),
+                SECTION_CURRENT => current = payload.usize()?,
+                SECTION_STACK => stack = payload.values()?,
+                SECTION_CALLS => calls = payload.values()?,
+                SECTION_REGISTERS => {
+                    bank.registers = payload
+                        .values()?
+                        .try_into()
+                        .map_err(|_| MachineError::SnapshotInvalid)?;
+                }
+                SECTION_HANDLERS => {
+                    while !payload.buffer.is_empty() {
+                        handlers.push(Handler {
+                            target: payload.usize()?,
+                            stack: payload.usize()?,
+                            calls: payload.usize()?,
+                        });
+                    }
+                }
+                SECTION_FLAGS => catch_faults = payload.u8()? != 0,
+                _ => {}
+            }
+        }
+
+        if seen != SECTIONS {
+            return Err(MachineError::SnapshotInvalid);
+        }
+        match fingerprint {
+            Some(fingerprint) if fingerprint == self.code.fingerprint() => {}
+            Some(_) => return Err(MachineError::SnapshotMismatch),
+            None => return Err(MachineError::SnapshotInvalid),
+        }
+
+        if self.recording.is_some() {
+            self.record(|_| Event::Restore(buffer.to_vec()));
+        }
+        let recording = self.recording.take();
+        self.reset();
+        self.recording = recording;
+        self.current = current;
+        self.stack = stack;
+        self.calls = calls;
+        self.bank = bank;
+        self.handlers = handlers;
+        self.catch_faults = catch_faults;
+        Ok(())
+    }
+}
+
+pub(crate) fn section(buffer: &mut Vec<u8>, tag: u8, write: impl FnOnce(&mut Vec<u8>)) {
+    buffer.push(tag);
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; 4]);
+    write(buffer);
+    let length = (buffer.len() - start - 4) as u32;
+    buffer[start..start + 4].copy_from_slice(&length.to_le_bytes());
+}
+
+pub(crate) fn values(buffer: &mut Vec<u8>, values: &[MachineValue]) {
+    let mut encoded = [0; MachineValue::encoded_len()];
+    for value in values {
+        value.encode(&mut encoded);
+        buffer.extend_from_slice(&encoded);
+    }
+}
+
+pub(crate) struct Reader<'buffer> {
+    pub(crate) buffer: &'buffer [u8],
+    pub(crate) error: MachineError,
+}
+
+impl<'buffer> Reader<'buffer> {
+    pub(crate) fn new(buffer: &'buffer [u8], error: MachineError) -> Reader<'buffer> {
+        Self { buffer, error }
+    }
+
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'buffer [u8]> {
+        if self.buffer.len() < length {
+            return Err(self.error.clone());
+        }
+        let (head, tail) = self.buffer.split_at(length);
+        self.buffer = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| self.error.clone())
+    }
+
+    pub(crate) fn values(&mut self) -> Result<Vec<MachineValue>> {
+        let mut values = Vec::new();
+        while !self.buffer.is_empty() {
+            let value = MachineValue::decode(self.take(MachineValue::encoded_len())?)
+                .ok_or_else(|| self.error.clone())?;
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::MachineError;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Handler, Machine, MachineLoopState};
+    use crate::op::OpArg::{Register3, Uint64};
+    use crate::op::OpCode::{Call, Exit, Native, Pop, Push, Return, TryBegin};
+    use crate::{op, program};
+
+    #[test]
+    fn snapshots_restore_the_whole_machine() {
+        let program = program!(
+            op!(TryBegin, handler),
+            op!(Push, Uint64(5)),
+            op!(Pop, Register3),
+            op!(Push, Uint64(7)),
+            op!(Call, callee),
+            op!(Exit),
+            handler:
+            op!(Exit),
+            callee:
+            op!(Native, Uint64(1)),
+            op!(Return),
+        );
+        let mut machine = Machine::new(&program);
+        machine.set_catch_faults(true);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Native(1)));
+
+        let snapshot = machine.snapshot();
+        let mut restored = Machine::restore(&program, &snapshot).unwrap();
+        assert_eq!(restored.pc(), 8);
+        assert_eq!(restored.stack(), [MachineValue::Uint64(7)]);
+        assert!(restored.calls().eq(machine.calls()));
+        assert_eq!(restored.register(2), Some(MachineValue::Uint64(5)));
+        assert_eq!(
+            restored.handlers,
+            [Handler {
+                target: 6,
+                stack: 0,
+                calls: 0,
+            }]
+        );
+        assert!(restored.catch_faults);
+        assert_eq!(restored.snapshot(), snapshot);
+
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(restored.run(), Ok(MachineLoopState::Break));
+        assert_eq!(restored.snapshot(), machine.snapshot());
+    }
+
+    #[test]
+    fn snapshots_of_other_programs_are_rejected() {
+        let program = program!(op!(Push, Uint64(1)), op!(Exit));
+        let other = program!(op!(Push, Uint64(2)), op!(Exit));
+        let snapshot = Machine::new(&program).snapshot();
+        assert_eq!(
+            Machine::restore(&other, &snapshot).unwrap_err(),
+            MachineError::SnapshotMismatch
+        );
+    }
+
+    #[test]
+    fn damaged_snapshots_are_rejected() {
+        let program = program!(op!(Push, Uint64(1)), op!(Push, Uint64(2)), op!(Exit));
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        let snapshot = machine.snapshot();
+
+        for length in 0..snapshot.len() {
+            assert_eq!(
+                Machine::restore(&program, &snapshot[..length]).unwrap_err(),
+                MachineError::SnapshotInvalid,
+                "truncated to {} bytes",
+                length
+            );
+        }
+
+        let mut magic = snapshot.clone();
+        magic[0] = b'X';
+        assert_eq!(
+            Machine::restore(&program, &magic).unwrap_err(),
+            MachineError::SnapshotInvalid
+        );
+
+        let stack = 4 + 2 + (1 + 4 + 8) + (1 + 4 + 8) + 1 + 4;
+        let mut value = snapshot.clone();
+        value[stack] = 0xff;
+        assert_eq!(
+            Machine::restore(&program, &value).unwrap_err(),
+            MachineError::SnapshotInvalid
+        );
+
+        let mut length = snapshot.clone();
+        length[stack - 4] += 1;
+        assert_eq!(
+            Machine::restore(&program, &length).unwrap_err(),
+            MachineError::SnapshotInvalid
+        );
+
+        assert_eq!(
+            machine.load_snapshot(&value),
+            Err(MachineError::SnapshotInvalid)
+        );
+        assert_eq!(
+            machine.stack(),
+            [MachineValue::Uint64(1), MachineValue::Uint64(2)]
+        );
+    }
+}
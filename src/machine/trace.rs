@@ -0,0 +1,208 @@
+use crate::error::MachineError;
+use crate::machine::observer::MachineObserver;
+use crate::machine::{MachineState, RegisterBank};
+use crate::op::{Op, OpArg};
+use std::fmt::Display;
+use std::io::{Result as IoResult, Write};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct TraceOptions {
+    pub stack: usize,
+    pub registers: bool,
+    pub depth: bool,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        Self {
+            stack: 4,
+            registers: true,
+            depth: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    writer: W,
+    options: TraceOptions,
+    bank: RegisterBank,
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Tracer<W> {
+        Self::with_options(writer, TraceOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: TraceOptions) -> Tracer<W> {
+        Self {
+            writer,
+            options,
+            bank: RegisterBank::new(),
+            error: None,
+        }
+    }
+
+    pub fn finish(mut self) -> IoResult<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn record(&mut self, write: impl FnOnce(&mut Self) -> IoResult<()>) {
+        if self.error.is_none()
+            && let Err(error) = write(self)
+        {
+            self.error = Some(error);
+        }
+    }
+
+    fn write_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) -> IoResult<()> {
+        write!(self.writer, "{{\"pc\":{},\"op\":", pc)?;
+        write_string(&mut self.writer, op.code)?;
+        if op.arg != OpArg::None {
+            write!(self.writer, ",\"arg\":")?;
+            write_string(&mut self.writer, op.arg)?;
+        }
+
+        if self.options.stack > 0 {
+            write!(self.writer, ",\"stack\":[")?;
+            let start = state.stack.len().saturating_sub(self.options.stack);
+            for (i, value) in state.stack[start..].iter().enumerate() {
+                if i > 0 {
+                    write!(self.writer, ",")?;
+                }
+                write_string(&mut self.writer, value)?;
+            }
+            write!(self.writer, "]")?;
+        }
+
+        if self.options.registers {
+            write!(self.writer, ",\"registers\":{{")?;
+            let mut first = true;
+            for (i, value) in state.bank.registers.iter().enumerate() {
+                if value.identical(self.bank.registers[i]) {
+                    continue;
+                }
+                if !first {
+                    write!(self.writer, ",")?;
+                }
+                first = false;
+                write!(self.writer, "\"r{}\":", i + 1)?;
+                write_string(&mut self.writer, value)?;
+            }
+            write!(self.writer, "}}")?;
+            self.bank = *state.bank;
+        }
+
+        if self.options.depth {
+            write!(self.writer, ",\"depth\":{}", state.calls.len())?;
+        }
+        writeln!(self.writer, "}}")
+    }
+
+    fn write_error(&mut self, pc: usize, error: &MachineError) -> IoResult<()> {
+        write!(self.writer, "{{\"pc\":{},\"error\":", pc)?;
+        write_string(&mut self.writer, error)?;
+        writeln!(self.writer, "}}")
+    }
+}
+
+impl<W: Write> MachineObserver for Tracer<W> {
+    fn after_step(&mut self, pc: usize, op: &Op, state: &MachineState<'_>) {
+        self.record(|tracer| tracer.write_step(pc, op, state));
+    }
+
+    fn on_error(&mut self, pc: usize, error: &MachineError) {
+        self.record(|tracer| tracer.write_error(pc, error));
+    }
+}
+
+fn write_string(writer: &mut impl Write, value: impl Display) -> IoResult<()> {
+    let value = value.to_string();
+    write!(writer, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(writer, "\\\"")?,
+            '\\' => write!(writer, "\\\\")?,
+            '\n' => write!(writer, "\\n")?,
+            c if c.is_control() => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    write!(writer, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceOptions, Tracer, write_string};
+    use crate::machine::Machine;
+    use crate::op::OpArg::{Register1, Register2, Uint64};
+    use crate::op::OpCode::{Call, Divide, Pop, Push, Return};
+    use crate::{op, program};
+
+    fn trace(options: TraceOptions) -> String {
+        let program = program!(
+            op!(Push, Uint64(6)),
+            op!(Pop, Register1),
+            op!(Push, Uint64(0)),
+            op!(Pop, Register2),
+            op!(Call, divide),
+            divide:
+            op!(Push, Register1),
+            op!(Push, Register2),
+            op!(Divide),
+            op!(Return),
+        );
+        let mut machine =
+            Machine::with_observer(&program, Tracer::with_options(Vec::new(), options));
+        assert!(machine.run().is_err());
+        String::from_utf8(machine.into_observer().finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn steps_are_json_lines() {
+        let lines = [
+            r#"{"pc":0,"op":"push","arg":"6u64","stack":["6u64"],"registers":{},"depth":0}"#,
+            r#"{"pc":1,"op":"pop","arg":"r1","stack":[],"registers":{"r1":"6u64"},"depth":0}"#,
+            r#"{"pc":2,"op":"push","arg":"0u64","stack":["0u64"],"registers":{},"depth":0}"#,
+            r#"{"pc":3,"op":"pop","arg":"r2","stack":[],"registers":{"r2":"0u64"},"depth":0}"#,
+            r#"{"pc":4,"op":"call","arg":"@5","stack":[],"registers":{},"depth":1}"#,
+            r#"{"pc":5,"op":"push","arg":"r1","stack":["6u64"],"registers":{},"depth":1}"#,
+            r#"{"pc":6,"op":"push","arg":"r2","stack":["6u64","0u64"],"registers":{},"depth":1}"#,
+            r#"{"pc":7,"error":"division by zero"}"#,
+        ];
+        assert_eq!(
+            trace(TraceOptions::default()),
+            lines.map(|line| format!("{}\n", line)).concat()
+        );
+    }
+
+    #[test]
+    fn options_trim_each_line() {
+        let options = TraceOptions {
+            stack: 1,
+            registers: false,
+            depth: false,
+        };
+        let trace = trace(options);
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[1], r#"{"pc":1,"op":"pop","arg":"r1","stack":[]}"#);
+        assert_eq!(
+            lines[6],
+            r#"{"pc":6,"op":"push","arg":"r2","stack":["0u64"]}"#
+        );
+        assert_eq!(lines[7], r#"{"pc":7,"error":"division by zero"}"#);
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut buffer = Vec::new();
+        write_string(&mut buffer, "a\"b\\c\nd\u{1}").unwrap();
+        assert_eq!(buffer, br#""a\"b\\c\nd\u0001""#);
+    }
+}
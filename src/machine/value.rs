@@ -13,4 +13,5 @@ pub enum MachineValue {
     Int32(i32),
     Int64(i64),
     ReturnAddress(usize),
+    Fault(u32),
 }
@@ -1,6 +1,8 @@
 use crate::machine::RegisterBank;
 use crate::machine::value::MachineValue;
 use crate::op::OpArg;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::mem::discriminant;
 use std::ops::{Add, Div, Mul, Sub};
 
 impl MachineValue {
@@ -43,6 +45,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as u8,
             MachineValue::Int16(value) => value as u8,
             MachineValue::ReturnAddress(value) => value as u8,
+            MachineValue::Fault(value) => value as u8,
         }
     }
 
@@ -59,6 +62,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as u16,
             MachineValue::Int16(value) => value as u16,
             MachineValue::ReturnAddress(value) => value as u16,
+            MachineValue::Fault(value) => value as u16,
         }
     }
 
@@ -75,6 +79,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as u32,
             MachineValue::Int16(value) => value as u32,
             MachineValue::ReturnAddress(value) => value as u32,
+            MachineValue::Fault(value) => value,
         }
     }
 
@@ -91,6 +96,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as u64,
             MachineValue::Int16(value) => value as u64,
             MachineValue::ReturnAddress(value) => value as u64,
+            MachineValue::Fault(value) => value as u64,
         }
     }
 
@@ -107,6 +113,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value,
             MachineValue::Int16(value) => value as i8,
             MachineValue::ReturnAddress(value) => value as i8,
+            MachineValue::Fault(value) => value as i8,
         }
     }
 
@@ -123,6 +130,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as i16,
             MachineValue::Int16(value) => value,
             MachineValue::ReturnAddress(value) => value as i16,
+            MachineValue::Fault(value) => value as i16,
         }
     }
 
@@ -139,6 +147,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as i32,
             MachineValue::Int16(value) => value as i32,
             MachineValue::ReturnAddress(value) => value as i32,
+            MachineValue::Fault(value) => value as i32,
         }
     }
 
@@ -155,6 +164,7 @@ impl MachineValue {
             MachineValue::Int8(value) => value as i64,
             MachineValue::Int16(value) => value as i64,
             MachineValue::ReturnAddress(value) => value as i64,
+            MachineValue::Fault(value) => value as i64,
         }
     }
 }
@@ -192,6 +202,54 @@ macro_rules! perform_value_op {
                 MachineValue::ReturnAddress(lhs) => {
                     MachineValue::ReturnAddress(lhs $op $right.as_u64() as usize)
                 }
+                MachineValue::Fault(lhs) => MachineValue::Uint32(lhs $op $right.as_u32()),
+            },
+        }
+    };
+}
+
+macro_rules! perform_checked_value_op {
+    ($left:expr, $right:expr, $op:ident) => {
+        match ($left, $right) {
+            (MachineValue::Uint32(lhs), MachineValue::Uint32(rhs)) => {
+                MachineValue::Uint32(lhs.$op(rhs)?)
+            }
+            (MachineValue::Uint64(lhs), MachineValue::Uint64(rhs)) => {
+                MachineValue::Uint64(lhs.$op(rhs)?)
+            }
+            (MachineValue::Int32(lhs), MachineValue::Int32(rhs)) => {
+                MachineValue::Int32(lhs.$op(rhs)?)
+            }
+            (MachineValue::Int64(lhs), MachineValue::Int64(rhs)) => {
+                MachineValue::Int64(lhs.$op(rhs)?)
+            }
+
+            (MachineValue::Uint8(lhs), MachineValue::Uint8(rhs)) => {
+                MachineValue::Uint8(lhs.$op(rhs)?)
+            }
+            (MachineValue::Uint16(lhs), MachineValue::Uint16(rhs)) => {
+                MachineValue::Uint16(lhs.$op(rhs)?)
+            }
+
+            (MachineValue::Int8(lhs), MachineValue::Int8(rhs)) => MachineValue::Int8(lhs.$op(rhs)?),
+            (MachineValue::Int16(lhs), MachineValue::Int16(rhs)) => {
+                MachineValue::Int16(lhs.$op(rhs)?)
+            }
+            _ => match $left {
+                MachineValue::Uint32(lhs) => MachineValue::Uint32(lhs.$op($right.as_u32())?),
+                MachineValue::Uint64(lhs) => MachineValue::Uint64(lhs.$op($right.as_u64())?),
+                MachineValue::Int32(lhs) => MachineValue::Int32(lhs.$op($right.as_i32())?),
+                MachineValue::Int64(lhs) => MachineValue::Int64(lhs.$op($right.as_i64())?),
+
+                MachineValue::None => MachineValue::None,
+                MachineValue::Uint8(lhs) => MachineValue::Uint8(lhs.$op($right.as_u8())?),
+                MachineValue::Uint16(lhs) => MachineValue::Uint16(lhs.$op($right.as_u16())?),
+                MachineValue::Int8(lhs) => MachineValue::Int8(lhs.$op($right.as_i8())?),
+                MachineValue::Int16(lhs) => MachineValue::Int16(lhs.$op($right.as_i16())?),
+                MachineValue::ReturnAddress(lhs) => {
+                    MachineValue::ReturnAddress(lhs.$op($right.as_u64() as usize)?)
+                }
+                MachineValue::Fault(lhs) => MachineValue::Uint32(lhs.$op($right.as_u32())?),
             },
         }
     };
@@ -229,6 +287,77 @@ impl Div for MachineValue {
     }
 }
 
+impl MachineValue {
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        Some(perform_checked_value_op!(self, rhs, checked_div))
+    }
+
+    pub fn zero(self) -> Self {
+        perform_value_op!(self, self, -)
+    }
+
+    pub const fn encoded_len() -> usize {
+        size_of::<u8>() + size_of::<u64>()
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            MachineValue::None => 0,
+            MachineValue::Uint8(_) => 1,
+            MachineValue::Uint16(_) => 2,
+            MachineValue::Uint32(_) => 3,
+            MachineValue::Uint64(_) => 4,
+            MachineValue::Int8(_) => 5,
+            MachineValue::Int16(_) => 6,
+            MachineValue::Int32(_) => 7,
+            MachineValue::Int64(_) => 8,
+            MachineValue::ReturnAddress(_) => 9,
+            MachineValue::Fault(_) => 10,
+        }
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) {
+        buffer[0] = self.id();
+        let payload = match *self {
+            MachineValue::None => 0,
+            MachineValue::Uint8(value) => value as u64,
+            MachineValue::Uint16(value) => value as u64,
+            MachineValue::Uint32(value) => value as u64,
+            MachineValue::Uint64(value) => value,
+            MachineValue::Int8(value) => value as u64,
+            MachineValue::Int16(value) => value as u64,
+            MachineValue::Int32(value) => value as u64,
+            MachineValue::Int64(value) => value as u64,
+            MachineValue::ReturnAddress(value) => value as u64,
+            MachineValue::Fault(value) => value as u64,
+        };
+        buffer[1..9].copy_from_slice(&payload.to_le_bytes());
+    }
+
+    pub fn decode(buffer: &[u8]) -> Option<Self> {
+        let id = *buffer.first()?;
+        let payload = u64::from_le_bytes(buffer.get(1..9)?.try_into().ok()?);
+        Some(match id {
+            0 => MachineValue::None,
+            1 => MachineValue::Uint8(payload as u8),
+            2 => MachineValue::Uint16(payload as u16),
+            3 => MachineValue::Uint32(payload as u32),
+            4 => MachineValue::Uint64(payload),
+            5 => MachineValue::Int8(payload as i8),
+            6 => MachineValue::Int16(payload as i16),
+            7 => MachineValue::Int32(payload as i32),
+            8 => MachineValue::Int64(payload as i64),
+            9 => MachineValue::ReturnAddress(usize::try_from(payload).ok()?),
+            10 => MachineValue::Fault(u32::try_from(payload).ok()?),
+            _ => return None,
+        })
+    }
+
+    pub fn identical(self, other: Self) -> bool {
+        discriminant(&self) == discriminant(&other) && self == other
+    }
+}
+
 impl PartialEq for MachineValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -242,6 +371,8 @@ impl PartialEq for MachineValue {
             (MachineValue::Int8(lhs), MachineValue::Int8(rhs)) => lhs == rhs,
             (MachineValue::Int16(lhs), MachineValue::Int16(rhs)) => lhs == rhs,
             (MachineValue::ReturnAddress(lhs), MachineValue::ReturnAddress(rhs)) => lhs == rhs,
+            (MachineValue::Fault(lhs), MachineValue::Fault(rhs)) => lhs == rhs,
+            (MachineValue::Fault(_), _) | (_, MachineValue::Fault(_)) => false,
             _ => match self {
                 MachineValue::Uint32(value) => *value == other.as_u32(),
                 MachineValue::Uint64(value) => *value == other.as_u64(),
@@ -254,9 +385,28 @@ impl PartialEq for MachineValue {
                 MachineValue::Int8(value) => *value == other.as_i8(),
                 MachineValue::Int16(value) => *value == other.as_i16(),
                 MachineValue::ReturnAddress(value) => *value == other.as_u64() as usize,
+                MachineValue::Fault(_) => false,
             },
         }
     }
 }
 
 impl Eq for MachineValue {}
+
+impl Display for MachineValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            MachineValue::None => write!(f, "none"),
+            MachineValue::Uint8(value) => write!(f, "{}u8", value),
+            MachineValue::Uint16(value) => write!(f, "{}u16", value),
+            MachineValue::Uint32(value) => write!(f, "{}u32", value),
+            MachineValue::Uint64(value) => write!(f, "{}u64", value),
+            MachineValue::Int8(value) => write!(f, "{}i8", value),
+            MachineValue::Int16(value) => write!(f, "{}i16", value),
+            MachineValue::Int32(value) => write!(f, "{}i32", value),
+            MachineValue::Int64(value) => write!(f, "{}i64", value),
+            MachineValue::ReturnAddress(value) => write!(f, "@{}", value),
+            MachineValue::Fault(code) => write!(f, "fault({})", code),
+        }
+    }
+}
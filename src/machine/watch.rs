@@ -0,0 +1,59 @@
+use crate::machine::RegisterBank;
+use crate::machine::value::MachineValue;
+use crate::op::OpArg;
+use std::ptr::fn_addr_eq;
+
+#[derive(Clone, Copy, Debug)]
+pub enum WatchCondition {
+    Write,
+    Change,
+    Matches(fn(MachineValue) -> bool),
+}
+
+impl PartialEq for WatchCondition {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (WatchCondition::Write, WatchCondition::Write) => true,
+            (WatchCondition::Change, WatchCondition::Change) => true,
+            (WatchCondition::Matches(lhs), WatchCondition::Matches(rhs)) => fn_addr_eq(*lhs, *rhs),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for WatchCondition {}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Watchpoint {
+    Register(OpArg, WatchCondition),
+    StackDepth(usize),
+}
+
+impl Watchpoint {
+    pub fn triggered(
+        &self,
+        written: Option<OpArg>,
+        before: &RegisterBank,
+        after: &RegisterBank,
+        depths: (usize, usize),
+    ) -> bool {
+        match *self {
+            Watchpoint::Register(register, condition) => {
+                if written != Some(register) {
+                    return false;
+                }
+                let (Some(before), Some(after)) = (before.load(register), after.load(register))
+                else {
+                    return false;
+                };
+                match condition {
+                    WatchCondition::Write => true,
+                    WatchCondition::Change => !before.identical(after),
+                    WatchCondition::Matches(predicate) => predicate(after),
+                }
+            }
+
+            Watchpoint::StackDepth(threshold) => (depths.0 > threshold) != (depths.1 > threshold),
+        }
+    }
+}
@@ -1,13 +1,43 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, stdin, stdout};
+use std::process::exit;
 use std::time::Instant;
-use tinyvm::error::Result;
+use std::{env, fs};
+use tinyvm::error::FormatError;
 use tinyvm::machine::Machine;
+use tinyvm::machine::coverage::Coverage;
+use tinyvm::machine::debug::Debugger;
+use tinyvm::machine::journal::JournalOptions;
+use tinyvm::machine::profile::Profiler;
 use tinyvm::machine::value::MachineValue::Uint64;
+use tinyvm::program::Program;
 
 pub mod fib;
 
 const COUNT: usize = 1_000_000;
 
-fn main() -> Result<()> {
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => bench(),
+        [command, path] if command == "debug" => debug(path),
+        [command, path] if command == "coverage" => coverage(path),
+        [command, path] if command == "profile" => profile(path, None),
+        [command, path, folded] if command == "profile" => profile(path, Some(folded)),
+        [command, path, output] if command == "assemble" => assemble(path, output),
+        [command, path] if command == "disassemble" => disassemble(path),
+        _ => {
+            eprintln!(
+                "usage: tinyvm [debug <file> | coverage <file> | profile <file> [folded-output] \
+                 | assemble <source> <output> | disassemble <file>]"
+            );
+            exit(2);
+        }
+    }
+}
+
+fn bench() -> Result<(), Box<dyn Error>> {
     let mut machine = Machine::new(&fib::FIB);
 
     let time = Instant::now();
@@ -22,3 +52,61 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn load(path: &str) -> Result<Program, Box<dyn Error>> {
+    if path.ends_with(".tvs") {
+        return Ok(Program::assemble_file(path, &fs::read_to_string(path)?)?);
+    }
+    let buffer = fs::read(path)?;
+    match Program::decode(&buffer) {
+        Err(FormatError::Magic) => Ok(Program::decode_raw(&buffer)?),
+        result => Ok(result?),
+    }
+}
+
+fn assemble(path: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let program = Program::assemble_file(path, &fs::read_to_string(path)?)?;
+    fs::write(output, program.encode())?;
+    Ok(())
+}
+
+fn disassemble(path: &str) -> Result<(), Box<dyn Error>> {
+    load(path)?.write_assembly(&mut stdout().lock())?;
+    Ok(())
+}
+
+fn debug(path: &str) -> Result<(), Box<dyn Error>> {
+    let program = load(path)?;
+    let mut machine = Machine::new(&program);
+    machine.enable_journal(JournalOptions::default());
+    let mut debugger = Debugger::new(machine, stdout());
+    debugger.run(stdin().lock())?;
+    Ok(())
+}
+
+fn coverage(path: &str) -> Result<(), Box<dyn Error>> {
+    let program = load(path)?;
+    let mut machine = Machine::with_observer(&program, Coverage::new());
+    let result = machine.run();
+    let coverage = machine.into_observer();
+    coverage.write_lcov(
+        &program,
+        program.source().unwrap_or(path),
+        &mut stdout().lock(),
+    )?;
+    result?;
+    Ok(())
+}
+
+fn profile(path: &str, folded: Option<&String>) -> Result<(), Box<dyn Error>> {
+    let program = load(path)?;
+    let mut machine = Machine::with_observer(&program, Profiler::for_program(&program));
+    let result = machine.run();
+    let profiler = machine.into_observer();
+    profiler.write_report(&program, &mut stdout().lock(), 20)?;
+    if let Some(folded) = folded {
+        profiler.write_folded(&mut BufWriter::new(File::create(folded)?))?;
+    }
+    result?;
+    Ok(())
+}
@@ -15,6 +15,10 @@ pub enum OpCode {
     Call = 9,
     Return = 10,
     Jump = 11,
+    Native = 12,
+    Throw = 13,
+    TryBegin = 14,
+    TryEnd = 15,
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
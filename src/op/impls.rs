@@ -1,30 +1,97 @@
+use crate::error::{DecodeError, DecodeReason, ParseArgError};
 use crate::op::{Op, OpArg, OpCode};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+const COMPACT_ARG: u8 = 0x80;
 
 impl OpCode {
     pub const fn encoded_len() -> usize {
         size_of::<u8>()
     }
 
-    pub const fn decode(id: u8) -> Option<OpCode> {
+    pub const fn decode(id: u8) -> Result<OpCode, DecodeError> {
         match id {
-            0 => Some(OpCode::Push),
-            1 => Some(OpCode::Pop),
-            2 => Some(OpCode::Add),
-            3 => Some(OpCode::Subtract),
-            4 => Some(OpCode::Multiply),
-            5 => Some(OpCode::Divide),
-            6 => Some(OpCode::JumpIfEqual),
-            7 => Some(OpCode::Exit),
-            8 => Some(OpCode::JumpIfZero),
-            9 => Some(OpCode::Call),
-            10 => Some(OpCode::Return),
-            _ => None,
+            0 => Ok(OpCode::Push),
+            1 => Ok(OpCode::Pop),
+            2 => Ok(OpCode::Add),
+            3 => Ok(OpCode::Subtract),
+            4 => Ok(OpCode::Multiply),
+            5 => Ok(OpCode::Divide),
+            6 => Ok(OpCode::JumpIfEqual),
+            7 => Ok(OpCode::Exit),
+            8 => Ok(OpCode::JumpIfZero),
+            9 => Ok(OpCode::Call),
+            10 => Ok(OpCode::Return),
+            11 => Ok(OpCode::Jump),
+            12 => Ok(OpCode::Native),
+            13 => Ok(OpCode::Throw),
+            14 => Ok(OpCode::TryBegin),
+            15 => Ok(OpCode::TryEnd),
+            _ => Err(DecodeError::new(0, DecodeReason::UnknownOpCode(id))),
         }
     }
 
     pub const fn encode(&self) -> u8 {
         *self as u8
     }
+
+    pub const fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::Push => "push",
+            OpCode::Pop => "pop",
+            OpCode::Add => "add",
+            OpCode::Subtract => "sub",
+            OpCode::Multiply => "mul",
+            OpCode::Divide => "div",
+            OpCode::JumpIfEqual => "jeq",
+            OpCode::Exit => "exit",
+            OpCode::JumpIfZero => "jz",
+            OpCode::Call => "call",
+            OpCode::Return => "ret",
+            OpCode::Jump => "jmp",
+            OpCode::Native => "native",
+            OpCode::Throw => "throw",
+            OpCode::TryBegin => "try",
+            OpCode::TryEnd => "endtry",
+        }
+    }
+
+    pub fn from_mnemonic(text: &str) -> Option<OpCode> {
+        match text {
+            "push" => Some(OpCode::Push),
+            "pop" => Some(OpCode::Pop),
+            "add" => Some(OpCode::Add),
+            "sub" => Some(OpCode::Subtract),
+            "mul" => Some(OpCode::Multiply),
+            "div" => Some(OpCode::Divide),
+            "jeq" => Some(OpCode::JumpIfEqual),
+            "exit" => Some(OpCode::Exit),
+            "jz" => Some(OpCode::JumpIfZero),
+            "call" => Some(OpCode::Call),
+            "ret" => Some(OpCode::Return),
+            "jmp" => Some(OpCode::Jump),
+            "native" => Some(OpCode::Native),
+            "throw" => Some(OpCode::Throw),
+            "try" => Some(OpCode::TryBegin),
+            "endtry" => Some(OpCode::TryEnd),
+            _ => None,
+        }
+    }
+
+    pub const fn requires_argument(&self) -> bool {
+        matches!(
+            self,
+            OpCode::Push
+                | OpCode::Pop
+                | OpCode::JumpIfEqual
+                | OpCode::JumpIfZero
+                | OpCode::Call
+                | OpCode::Jump
+                | OpCode::Native
+                | OpCode::TryBegin
+        )
+    }
 }
 
 impl OpArg {
@@ -57,17 +124,17 @@ impl OpArg {
         }
     }
 
-    pub const fn decode(buffer: &[u8]) -> Option<OpArg> {
-        let id = buffer[0];
-        let v1 = buffer[1];
-        let v2 = buffer[2];
-        let v3 = buffer[3];
-        let v4 = buffer[4];
-        let v5 = buffer[5];
-        let v6 = buffer[6];
-        let v7 = buffer[7];
-        let v8 = buffer[8];
-        Some(match id {
+    pub const fn decode(buffer: &[u8]) -> Result<OpArg, DecodeError> {
+        let [id, ..] = *buffer else {
+            return Err(DecodeError::new(0, DecodeReason::Truncated));
+        };
+        if id > 18 {
+            return Err(DecodeError::new(0, DecodeReason::UnknownArgTag(id)));
+        }
+        let [_, v1, v2, v3, v4, v5, v6, v7, v8, ..] = *buffer else {
+            return Err(DecodeError::new(buffer.len(), DecodeReason::Truncated));
+        };
+        Ok(match id {
             0 => OpArg::Register1,
             1 => OpArg::Register2,
             2 => OpArg::Register3,
@@ -86,8 +153,7 @@ impl OpArg {
             15 => OpArg::Int16(i16::from_le_bytes([v1, v2])),
             16 => OpArg::Int32(i32::from_le_bytes([v1, v2, v3, v4])),
             17 => OpArg::Int64(i64::from_le_bytes([v1, v2, v3, v4, v5, v6, v7, v8])),
-            18 => OpArg::Instruction(u64::from_le_bytes([v1, v2, v3, v4, v5, v6, v7, v8])),
-            _ => return None,
+            _ => OpArg::Instruction(u64::from_le_bytes([v1, v2, v3, v4, v5, v6, v7, v8])),
         })
     }
 
@@ -150,6 +216,77 @@ impl OpArg {
         buffer[0] = self.id();
         self.encode_value(&mut buffer[1..]);
     }
+
+    pub fn encode_compact(&self, buffer: &mut Vec<u8>) {
+        buffer.push(self.id());
+        match *self {
+            OpArg::Uint8(value) => write_varint(buffer, value as u64),
+            OpArg::Uint16(value) => write_varint(buffer, value as u64),
+            OpArg::Uint32(value) => write_varint(buffer, value as u64),
+            OpArg::Uint64(value) | OpArg::Instruction(value) => write_varint(buffer, value),
+            OpArg::Int8(value) => write_varint(buffer, zigzag(value as i64)),
+            OpArg::Int16(value) => write_varint(buffer, zigzag(value as i64)),
+            OpArg::Int32(value) => write_varint(buffer, zigzag(value as i64)),
+            OpArg::Int64(value) => write_varint(buffer, zigzag(value)),
+            _ => {}
+        }
+    }
+
+    pub fn decode_compact(buffer: &[u8]) -> Result<(OpArg, usize), DecodeError> {
+        let Some(&id) = buffer.first() else {
+            return Err(DecodeError::new(0, DecodeReason::Truncated));
+        };
+        let (value, length) = match id {
+            10..=18 => read_varint(&buffer[1..]).map_err(|error| error.at(1, 0))?,
+            19.. => return Err(DecodeError::new(0, DecodeReason::UnknownArgTag(id))),
+            _ => (0, 0),
+        };
+        let invalid = |_| DecodeError::new(1, DecodeReason::InvalidValue);
+        let arg = match id {
+            10 => OpArg::Uint8(value.try_into().map_err(invalid)?),
+            11 => OpArg::Uint16(value.try_into().map_err(invalid)?),
+            12 => OpArg::Uint32(value.try_into().map_err(invalid)?),
+            13 => OpArg::Uint64(value),
+            14 => OpArg::Int8(unzigzag(value).try_into().map_err(invalid)?),
+            15 => OpArg::Int16(unzigzag(value).try_into().map_err(invalid)?),
+            16 => OpArg::Int32(unzigzag(value).try_into().map_err(invalid)?),
+            17 => OpArg::Int64(unzigzag(value)),
+            18 => OpArg::Instruction(value),
+            id => OpArg::decode(&[id, 0, 0, 0, 0, 0, 0, 0, 0])?,
+        };
+        Ok((arg, 1 + length))
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(buffer: &[u8]) -> Result<(u64, usize), DecodeError> {
+    let mut value = 0u64;
+    for (i, byte) in buffer.iter().enumerate() {
+        let bits = (*byte & 0x7f) as u64;
+        if i > 9 || (i == 9 && bits > 1) {
+            return Err(DecodeError::new(i, DecodeReason::InvalidValue));
+        }
+        value |= bits << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(DecodeError::new(buffer.len(), DecodeReason::Truncated))
 }
 
 impl Op {
@@ -157,22 +294,251 @@ impl Op {
         Self { code, arg }
     }
 
-    pub fn decode(buffer: &[u8]) -> Option<Self> {
-        if buffer.len() != Self::encoded_len() {
-            return None;
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let Some(&byte) = buffer.first() else {
+            return Err(DecodeError::new(0, DecodeReason::Truncated));
+        };
+        let code = OpCode::decode(byte)?;
+        let arg = OpArg::decode(&buffer[1..]).map_err(|error| error.at(1, 0))?;
+        if buffer.len() > Self::encoded_len() {
+            return Err(DecodeError::new(
+                Self::encoded_len(),
+                DecodeReason::TrailingBytes(buffer.len() - Self::encoded_len()),
+            ));
         }
-        let code = OpCode::decode(buffer[0])?;
-        let arg = OpArg::decode(&buffer[1..])?;
-        Some(Op::new(code, arg))
+        Ok(Op::new(code, arg))
     }
 
     pub const fn encoded_len() -> usize {
         OpCode::encoded_len() + OpArg::encoded_len()
     }
 
+    pub fn encode_compact(&self, buffer: &mut Vec<u8>) {
+        if self.arg == OpArg::None {
+            buffer.push(self.code.encode());
+        } else {
+            buffer.push(self.code.encode() | COMPACT_ARG);
+            self.arg.encode_compact(buffer);
+        }
+    }
+
+    pub fn decode_compact(buffer: &[u8]) -> Result<(Op, usize), DecodeError> {
+        let Some(&byte) = buffer.first() else {
+            return Err(DecodeError::new(0, DecodeReason::Truncated));
+        };
+        let code = OpCode::decode(byte & !COMPACT_ARG)?;
+        if byte & COMPACT_ARG == 0 {
+            return Ok((Op::new(code, OpArg::None), 1));
+        }
+        let (arg, length) = OpArg::decode_compact(&buffer[1..]).map_err(|error| error.at(1, 0))?;
+        if arg == OpArg::None {
+            return Err(DecodeError::new(1, DecodeReason::InvalidValue));
+        }
+        Ok((Op::new(code, arg), 1 + length))
+    }
+
     pub fn encode(&self, buffer: &mut [u8]) {
         buffer[0] = self.code.encode();
         let argument = &mut buffer[1..];
         self.arg.encode(argument);
     }
 }
+
+impl Display for OpCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.mnemonic())
+    }
+}
+
+impl Display for OpArg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            OpArg::Register1 => write!(f, "r1"),
+            OpArg::Register2 => write!(f, "r2"),
+            OpArg::Register3 => write!(f, "r3"),
+            OpArg::Register4 => write!(f, "r4"),
+            OpArg::Register5 => write!(f, "r5"),
+            OpArg::Register6 => write!(f, "r6"),
+            OpArg::Register7 => write!(f, "r7"),
+            OpArg::Register8 => write!(f, "r8"),
+            OpArg::Register9 => write!(f, "r9"),
+            OpArg::None => write!(f, "none"),
+            OpArg::Uint8(value) => write!(f, "{}u8", value),
+            OpArg::Uint16(value) => write!(f, "{}u16", value),
+            OpArg::Uint32(value) => write!(f, "{}u32", value),
+            OpArg::Uint64(value) => write!(f, "{}u64", value),
+            OpArg::Int8(value) => write!(f, "{}i8", value),
+            OpArg::Int16(value) => write!(f, "{}i16", value),
+            OpArg::Int32(value) => write!(f, "{}i32", value),
+            OpArg::Int64(value) => write!(f, "{}i64", value),
+            OpArg::Instruction(value) => write!(f, "@{}", value),
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.arg {
+            OpArg::None => write!(f, "{}", self.code),
+            arg => write!(f, "{} {}", self.code, arg),
+        }
+    }
+}
+
+impl FromStr for OpArg {
+    type Err = ParseArgError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || ParseArgError(text.to_string());
+        let arg = match text {
+            "r1" => OpArg::Register1,
+            "r2" => OpArg::Register2,
+            "r3" => OpArg::Register3,
+            "r4" => OpArg::Register4,
+            "r5" => OpArg::Register5,
+            "r6" => OpArg::Register6,
+            "r7" => OpArg::Register7,
+            "r8" => OpArg::Register8,
+            "r9" => OpArg::Register9,
+            "none" => OpArg::None,
+            _ => {
+                if let Some(instruction) = text.strip_prefix('@') {
+                    return instruction
+                        .parse()
+                        .map(OpArg::Instruction)
+                        .map_err(|_| error());
+                }
+                let split = text.find(['u', 'i']).unwrap_or(text.len());
+                let (value, suffix) = text.split_at(split);
+                match suffix {
+                    "u8" => OpArg::Uint8(value.parse().map_err(|_| error())?),
+                    "u16" => OpArg::Uint16(value.parse().map_err(|_| error())?),
+                    "u32" => OpArg::Uint32(value.parse().map_err(|_| error())?),
+                    "u64" => OpArg::Uint64(value.parse().map_err(|_| error())?),
+                    "i8" => OpArg::Int8(value.parse().map_err(|_| error())?),
+                    "i16" => OpArg::Int16(value.parse().map_err(|_| error())?),
+                    "i32" => OpArg::Int32(value.parse().map_err(|_| error())?),
+                    "i64" => OpArg::Int64(value.parse().map_err(|_| error())?),
+                    "" if value.starts_with('-') => {
+                        OpArg::Int64(value.parse().map_err(|_| error())?)
+                    }
+                    "" => OpArg::Uint64(value.parse().map_err(|_| error())?),
+                    _ => return Err(error()),
+                }
+            }
+        };
+        Ok(arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{DecodeError, DecodeReason, FormatError};
+    use crate::op;
+    use crate::op::OpArg::*;
+    use crate::op::OpCode::{Exit, Jump, Push};
+    use crate::op::{Op, OpArg};
+    use crate::program::Program;
+
+    fn round_trip(op: Op) -> usize {
+        let mut buffer = Vec::new();
+        op.encode_compact(&mut buffer);
+        assert_eq!(
+            Op::decode_compact(&buffer),
+            Ok((op, buffer.len())),
+            "{:?}",
+            op
+        );
+        buffer.len()
+    }
+
+    #[test]
+    fn compact_ops_round_trip_at_the_limits() {
+        let args = [
+            Register1,
+            Register9,
+            Uint8(0),
+            Uint8(u8::MAX),
+            Uint16(u16::MAX),
+            Uint32(u32::MAX),
+            Uint64(0),
+            Uint64(u64::MAX),
+            Int8(i8::MIN),
+            Int8(i8::MAX),
+            Int16(i16::MIN),
+            Int32(i32::MIN),
+            Int64(i64::MIN),
+            Int64(i64::MAX),
+            Int64(-1),
+            Instruction(u64::MAX),
+        ];
+        for arg in args {
+            round_trip(op!(Push, arg));
+        }
+    }
+
+    #[test]
+    fn compact_ops_are_short() {
+        assert_eq!(round_trip(op!(Exit)), 1);
+        assert_eq!(round_trip(op!(Push, Register1)), 2);
+        assert_eq!(round_trip(op!(Push, Uint64(127))), 3);
+        assert_eq!(round_trip(op!(Push, Uint64(128))), 4);
+        assert_eq!(round_trip(op!(Push, Int64(-64))), 3);
+        assert_eq!(round_trip(op!(Jump, Instruction(300))), 4);
+        assert_eq!(round_trip(op!(Push, Uint64(u64::MAX))), 12);
+    }
+
+    #[test]
+    fn malformed_compact_ops_are_rejected() {
+        let error = |offset, reason| Err(DecodeError::new(offset, reason));
+        assert_eq!(Op::decode_compact(&[]), error(0, DecodeReason::Truncated));
+        assert_eq!(
+            Op::decode_compact(&[0x7f]),
+            error(0, DecodeReason::UnknownOpCode(0x7f))
+        );
+        assert_eq!(
+            Op::decode_compact(&[0x80]),
+            error(1, DecodeReason::Truncated)
+        );
+        assert_eq!(
+            Op::decode_compact(&[0x80, 19]),
+            error(1, DecodeReason::UnknownArgTag(19))
+        );
+        assert_eq!(
+            Op::decode_compact(&[0x80, 13, 0x80]),
+            error(3, DecodeReason::Truncated)
+        );
+        assert_eq!(
+            Op::decode_compact(&[0x80, 9]),
+            error(1, DecodeReason::InvalidValue),
+            "an explicit none argument"
+        );
+        assert_eq!(
+            Op::decode_compact(&[0x80, 10, 0x80, 0x02]),
+            error(2, DecodeReason::InvalidValue),
+            "256 does not fit in a u8"
+        );
+        let mut overflow = vec![0x80, 13];
+        overflow.extend([0xff; 9]);
+        overflow.push(0x02);
+        assert_eq!(
+            Op::decode_compact(&overflow),
+            error(11, DecodeReason::InvalidValue)
+        );
+        assert_eq!(OpArg::decode_compact(&[13, 0x01]), Ok((Uint64(1), 2)));
+    }
+
+    #[test]
+    fn compact_programs_report_the_failing_instruction() {
+        let program = Program::new(vec![op!(Push, Uint64(1000)), op!(Exit), op!(Exit)]);
+        let mut buffer = program.encode_compact();
+        assert_eq!(Program::decode_compact(&buffer).unwrap(), program);
+
+        buffer[4] = 0x7f;
+        let Err(FormatError::Decode(error)) = Program::decode_compact(&buffer) else {
+            panic!("expected a decode error");
+        };
+        assert_eq!((error.offset, error.index), (4, 1));
+        assert_eq!(error.reason, DecodeReason::UnknownOpCode(0x7f));
+    }
+}
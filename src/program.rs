@@ -1,47 +1,257 @@
+use crate::machine::value::MachineValue;
 use crate::op::Op;
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+
+mod assembly;
+pub mod builder;
+mod container;
+pub mod link;
+pub mod view;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Function,
+    Label,
+    Constant,
+    Global,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub address: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Global {
+    pub name: String,
+    pub value: MachineValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Function {
+    pub name: String,
+    pub entry: usize,
+    pub len: usize,
+    pub arity: u32,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub enum Program {
-    Owned(Vec<Op>),
-    Static(&'static [Op]),
+pub struct Export {
+    pub name: String,
+    pub address: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LineInfo {
+    pub pc: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    code: Cow<'static, [Op]>,
+    constants: Vec<MachineValue>,
+    globals: Vec<Global>,
+    symbols: Vec<Symbol>,
+    functions: Vec<Function>,
+    exports: Vec<Export>,
+    source: Option<String>,
+    lines: Vec<LineInfo>,
+}
+
+impl Hash for Program {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.code.hash(state);
+        self.constants.len().hash(state);
+        for global in &self.globals {
+            global.name.hash(state);
+        }
+        self.symbols.hash(state);
+        self.functions.hash(state);
+        self.exports.hash(state);
+        self.source.hash(state);
+        self.lines.hash(state);
+    }
 }
 
 impl Program {
     pub fn new(ops: Vec<Op>) -> Self {
-        Self::Owned(ops)
+        Self::with_code(Cow::Owned(ops))
     }
 
-    pub fn ops(&self) -> &[Op] {
-        match self {
-            Self::Owned(ops) => ops,
-            Self::Static(ops) => ops,
-        }
+    pub const fn from_static(ops: &'static [Op]) -> Self {
+        Self::with_code(Cow::Borrowed(ops))
     }
 
-    pub fn decode(buffer: &[u8]) -> Option<Self> {
-        let mut ops = Vec::new();
-        for i in 0..buffer.len() / Op::encoded_len() {
-            let op = Op::decode(
-                &buffer[i * Op::encoded_len()..i * Op::encoded_len() + Op::encoded_len()],
-            )?;
-            ops.push(op);
+    const fn with_code(code: Cow<'static, [Op]>) -> Self {
+        Self {
+            code,
+            constants: Vec::new(),
+            globals: Vec::new(),
+            symbols: Vec::new(),
+            functions: Vec::new(),
+            exports: Vec::new(),
+            source: None,
+            lines: Vec::new(),
         }
-        Some(Self::Owned(ops))
     }
 
-    pub fn encode(&self) -> Vec<u8> {
-        let mut buffer = vec![0; self.ops().len() * Op::encoded_len()];
-        for (i, op) in self.ops().iter().enumerate() {
-            op.encode(&mut buffer[i * Op::encoded_len()..]);
+    pub fn ops(&self) -> &[Op] {
+        &self.code
+    }
+
+    pub fn get(&self, pc: usize) -> Option<Op> {
+        self.code.get(pc).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Op> + '_ {
+        self.code.iter().copied()
+    }
+
+    pub fn constants(&self) -> &[MachineValue] {
+        &self.constants
+    }
+
+    pub fn add_constant(&mut self, value: MachineValue) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    pub fn add_global(&mut self, name: impl Into<String>, value: MachineValue) -> usize {
+        self.globals.push(Global {
+            name: name.into(),
+            value,
+        });
+        self.globals.len() - 1
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    pub fn symbol_at(&self, kind: SymbolKind, address: usize) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.kind == kind && symbol.address == address)
+    }
+
+    pub fn add_symbol(&mut self, name: impl Into<String>, kind: SymbolKind, address: usize) {
+        let index = self
+            .symbols
+            .partition_point(|symbol| (symbol.kind as u8, symbol.address) <= (kind as u8, address));
+        self.symbols.insert(
+            index,
+            Symbol {
+                name: name.into(),
+                kind,
+                address,
+            },
+        );
+    }
+
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    pub fn add_function(&mut self, name: impl Into<String>, entry: usize, len: usize, arity: u32) {
+        self.functions.push(Function {
+            name: name.into(),
+            entry,
+            len,
+            arity,
+        });
+    }
+
+    pub fn exports(&self) -> &[Export] {
+        &self.exports
+    }
+
+    pub fn export(&self, name: &str) -> Option<usize> {
+        self.exports
+            .iter()
+            .find(|export| export.name == name)
+            .map(|export| export.address)
+    }
+
+    pub fn add_export(&mut self, name: impl Into<String>, address: usize) {
+        self.exports.push(Export {
+            name: name.into(),
+            address,
+        });
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn set_source(&mut self, source: impl Into<String>) {
+        self.source = Some(source.into());
+    }
+
+    pub fn lines(&self) -> &[LineInfo] {
+        &self.lines
+    }
+
+    pub fn line(&self, pc: usize) -> Option<LineInfo> {
+        let index = self.lines.partition_point(|info| info.pc <= pc);
+        index.checked_sub(1).map(|index| self.lines[index])
+    }
+
+    pub fn add_line(&mut self, pc: usize, line: u32, column: u32) {
+        let index = self.lines.partition_point(|info| info.pc <= pc);
+        self.lines.insert(index, LineInfo { pc, line, column });
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        fingerprint(self.iter())
+    }
+}
+
+pub(crate) fn fingerprint(ops: impl Iterator<Item = Op>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut buffer = [0; Op::encoded_len()];
+    for op in ops {
+        op.encode(&mut buffer);
+        for byte in buffer {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
         }
-        buffer
     }
+    hash
 }
 
 #[macro_export]
 macro_rules! program_static {
     ($($op:expr),+ $(,)?) => {
-        $crate::program::Program::Static(&[$($op),+])
+        $crate::program::Program::from_static(&[$($op),+])
+    };
+
+    ($($body:tt)+) => {
+        $crate::__program_ops!(@static [] [] (0) $($body)+)
     }
 }
 
@@ -49,5 +259,274 @@ macro_rules! program_static {
 macro_rules! program{
     ($($op:expr),+ $(,)?) => {
         $crate::program::Program::new(vec![$($op),+])
+    };
+
+    ($($body:tt)+) => {
+        $crate::__program_ops!(@owned [] [] (0) $($body)+)
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __program_ops {
+    (@$build:ident [$($labels:tt)*] [$($ops:tt)*] ($($index:tt)*) $label:ident : $($rest:tt)*) => {
+        $crate::__program_ops!(
+            @$build [$($labels)* $label = ($($index)*);] [$($ops)*] ($($index)*) $($rest)*
+        )
+    };
+
+    (
+        @$build:ident [$($labels:tt)*] [$($ops:tt)*] ($($index:tt)*)
+        $a:expr, $b:expr, $c:expr, $d:expr, $e:expr, $f:expr, $g:expr, $h:expr,
+        $i:expr, $j:expr, $k:expr, $l:expr, $m:expr, $n:expr, $o:expr, $p:expr, $($rest:tt)*
+    ) => {
+        $crate::__program_ops!(
+            @$build [$($labels)*]
+            [$($ops)* $a, $b, $c, $d, $e, $f, $g, $h, $i, $j, $k, $l, $m, $n, $o, $p,]
+            ($($index)* + 16) $($rest)*
+        )
+    };
+
+    (
+        @$build:ident [$($labels:tt)*] [$($ops:tt)*] ($($index:tt)*)
+        $a:expr, $b:expr, $c:expr, $d:expr, $($rest:tt)*
+    ) => {
+        $crate::__program_ops!(
+            @$build [$($labels)*] [$($ops)* $a, $b, $c, $d,] ($($index)* + 4) $($rest)*
+        )
+    };
+
+    (@$build:ident [$($labels:tt)*] [$($ops:tt)*] ($($index:tt)*) $op:expr $(, $($rest:tt)*)?) => {
+        $crate::__program_ops!(
+            @$build [$($labels)*] [$($ops)* $op,] ($($index)* + 1) $($($rest)*)?
+        )
+    };
+
+    (@static [$($label:ident = ($($index:tt)*);)*] [$($ops:tt)*] ($($count:tt)*)) => {{
+        $(
+            #[allow(non_upper_case_globals)]
+            const $label: $crate::op::OpArg = $crate::op::OpArg::Instruction($($index)*);
+        )*
+        $crate::program::Program::from_static(&[$($ops)*])
+    }};
+
+    (@owned [$($label:ident = ($($index:tt)*);)*] [$($ops:tt)*] ($($count:tt)*)) => {{
+        $(
+            #[allow(non_upper_case_globals)]
+            const $label: $crate::op::OpArg = $crate::op::OpArg::Instruction($($index)*);
+        )*
+        $crate::program::Program::new(vec![$($ops)*])
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::op;
+    use crate::op::OpArg::{Instruction, Uint64};
+    use crate::op::OpCode::{Jump, Push};
+    use crate::program::Program;
+
+    #[rustfmt::skip]
+    #[test]
+    fn long_programs_expand() {
+        static UNLABELLED: Program = program_static!(
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        );
+        let unlabelled = program!(
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        );
+        assert_eq!(UNLABELLED.len(), 200);
+        assert_eq!(unlabelled, UNLABELLED);
+
+        let labelled = program!(
+            first:
+            op!(Jump, end),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+            middle:
+            op!(Jump, first),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+        op!(Push, Uint64(1)), op!(Push, Uint64(1)),
+            op!(Jump, last),
+            last:
+            op!(Jump, middle),
+            end:
+        );
+        assert_eq!(labelled.len(), 304);
+        assert_eq!(labelled.get(0), Some(op!(Jump, Instruction(304))));
+        assert_eq!(labelled.get(151), Some(op!(Jump, Instruction(0))));
+        assert_eq!(labelled.get(302), Some(op!(Jump, Instruction(303))));
+        assert_eq!(labelled.get(303), Some(op!(Jump, Instruction(151))));
+        assert_eq!(labelled.iter().filter(|op| op.code == Push).count(), 300);
     }
 }
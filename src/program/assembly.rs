@@ -0,0 +1,809 @@
+use crate::error::{AssembleError, AssembleErrorKind};
+use crate::machine::RegisterBank;
+use crate::machine::value::MachineValue;
+use crate::op::{Op, OpArg, OpCode};
+use crate::program::{Program, Symbol, SymbolKind};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Result as IoResult, Write};
+use std::str::FromStr;
+
+const COMMENT: char = ';';
+const LOCAL_PREFIX: &str = ".L";
+const INDEX_COLUMN: usize = 28;
+
+impl Program {
+    pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+        Assembler::new(false).assemble(source)
+    }
+
+    pub fn assemble_file(path: &str, source: &str) -> Result<Program, AssembleError> {
+        let mut program = Assembler::new(true).assemble(source)?;
+        if program.source.is_none() {
+            program.set_source(path);
+        }
+        Ok(program)
+    }
+}
+
+impl Program {
+    pub fn disassemble(&self) -> String {
+        let mut buffer = Vec::new();
+        self.write_assembly(&mut buffer)
+            .expect("writing to a vec cannot fail");
+        String::from_utf8(buffer).expect("assembly is valid utf-8")
+    }
+
+    pub fn write_assembly(&self, writer: &mut impl Write) -> IoResult<()> {
+        let len = self.len();
+        let mut defined = HashSet::new();
+        let mut named = HashSet::new();
+        let inline: Vec<bool> = self
+            .symbols
+            .iter()
+            .map(|symbol| match symbol.kind {
+                SymbolKind::Function | SymbolKind::Label => {
+                    symbol.address <= len && defined.insert(symbol.name.as_str())
+                }
+                SymbolKind::Constant => {
+                    symbol.address < self.constants.len() && named.insert(symbol.address)
+                }
+                SymbolKind::Global => false,
+            })
+            .collect();
+
+        let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+        for (symbol, _) in self
+            .symbols
+            .iter()
+            .zip(&inline)
+            .filter(|(_, inline)| **inline)
+        {
+            if is_label(symbol.kind) {
+                labels
+                    .entry(symbol.address)
+                    .or_insert_with(|| quote(&symbol.name).into_owned());
+            }
+        }
+        let targets = self
+            .iter()
+            .filter_map(|op| match op.arg {
+                OpArg::Instruction(target) => Some(target as usize),
+                _ => None,
+            })
+            .chain(self.exports.iter().map(|export| export.address))
+            .filter(|target| *target <= len);
+        let mut synthesized = BTreeSet::new();
+        for target in targets {
+            labels.entry(target).or_insert_with(|| {
+                synthesized.insert(target);
+                let mut name = format!("{}{}", LOCAL_PREFIX, target);
+                while defined.contains(name.as_str()) {
+                    name.push('_');
+                }
+                name
+            });
+        }
+
+        if let Some(source) = &self.source {
+            writeln!(writer, ".source {}", quote_source(source))?;
+        }
+        for (index, value) in self.constants.iter().enumerate() {
+            let mut symbols = self
+                .symbols
+                .iter()
+                .filter(|symbol| symbol.kind == SymbolKind::Constant && symbol.address == index);
+            match symbols.next() {
+                Some(symbol) => writeln!(writer, ".const {} {}", quote(&symbol.name), value)?,
+                None => writeln!(writer, ".const {}", value)?,
+            }
+            for symbol in symbols {
+                write_symbol(writer, symbol)?;
+            }
+        }
+        for global in &self.globals {
+            writeln!(writer, ".global {} {}", quote(&global.name), global.value)?;
+        }
+
+        let mut lines = self.lines.iter().peekable();
+        for pc in 0..=len {
+            for (symbol, inline) in self.symbols.iter().zip(&inline) {
+                if symbol.address != pc || !is_label(symbol.kind) {
+                    continue;
+                }
+                match symbol.kind {
+                    _ if !inline => write_symbol(writer, symbol)?,
+                    SymbolKind::Function => writeln!(writer, "\n.func {}", quote(&symbol.name))?,
+                    _ => writeln!(writer, "{}:", quote(&symbol.name))?,
+                }
+            }
+            if synthesized.contains(&pc) {
+                writeln!(writer, "{}:", labels[&pc])?;
+            }
+            while let Some(info) = lines.next_if(|info| info.pc <= pc) {
+                if info.pc == pc {
+                    writeln!(writer, "    .line {} {}", info.line, info.column)?;
+                }
+            }
+            let Some(op) = self.get(pc) else {
+                continue;
+            };
+            let instruction = match op.arg {
+                OpArg::Instruction(target) => match labels.get(&(target as usize)) {
+                    Some(label) => format!("{} {}", op.code, label),
+                    None => op.to_string(),
+                },
+                OpArg::None if op.code.requires_argument() => format!("{} none", op.code),
+                _ => op.to_string(),
+            };
+            writeln!(
+                writer,
+                "    {:<width$}; #{}",
+                instruction,
+                pc,
+                width = INDEX_COLUMN
+            )?;
+        }
+
+        let detached: Vec<_> = self
+            .symbols
+            .iter()
+            .zip(&inline)
+            .filter(|(symbol, inline)| {
+                !**inline
+                    && match symbol.kind {
+                        SymbolKind::Function | SymbolKind::Label => symbol.address > len,
+                        SymbolKind::Constant => symbol.address >= self.constants.len(),
+                        SymbolKind::Global => true,
+                    }
+            })
+            .map(|(symbol, _)| symbol)
+            .collect();
+        if !self.exports.is_empty() || !self.functions.is_empty() || !detached.is_empty() {
+            writeln!(writer)?;
+        }
+        for symbol in detached {
+            write_symbol(writer, symbol)?;
+        }
+        for function in &self.functions {
+            writeln!(
+                writer,
+                ".function {} {} {} {}",
+                quote(&function.name),
+                function.entry,
+                function.len,
+                function.arity
+            )?;
+        }
+        for export in &self.exports {
+            let name = quote(&export.name);
+            match labels.get(&export.address) {
+                Some(label) if *label == name => writeln!(writer, ".export {}", label)?,
+                Some(label) => writeln!(writer, ".export {} {}", name, label)?,
+                None => writeln!(writer, ".export {} @{}", name, export.address)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_symbol(writer: &mut impl Write, symbol: &Symbol) -> IoResult<()> {
+    let kind = match symbol.kind {
+        SymbolKind::Function => "function",
+        SymbolKind::Label => "label",
+        SymbolKind::Constant => "constant",
+        SymbolKind::Global => "global",
+    };
+    writeln!(
+        writer,
+        ".symbol {} {} {}",
+        kind,
+        quote(&symbol.name),
+        symbol.address
+    )
+}
+
+#[derive(Clone, Copy)]
+struct Token<'source> {
+    text: &'source str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, kind: AssembleErrorKind) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+}
+
+struct Reference {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+impl Reference {
+    fn new(token: &Token<'_>) -> Result<Reference, AssembleError> {
+        Ok(Self {
+            name: name(token, token.text)?,
+            line: token.line,
+            column: token.column,
+        })
+    }
+}
+
+enum Target {
+    Address(usize),
+    Label(Reference),
+}
+
+struct Assembler {
+    ops: Vec<Op>,
+    program: Program,
+    labels: HashMap<String, usize>,
+    fixups: Vec<(usize, Reference)>,
+    exports: Vec<(String, Target)>,
+    lines: Vec<(u32, u32)>,
+    track_lines: bool,
+}
+
+impl Assembler {
+    fn new(track_lines: bool) -> Assembler {
+        Self {
+            ops: Vec::new(),
+            program: Program::new(Vec::new()),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            exports: Vec::new(),
+            lines: Vec::new(),
+            track_lines,
+        }
+    }
+
+    fn assemble(mut self, source: &str) -> Result<Program, AssembleError> {
+        for (index, text) in source.lines().enumerate() {
+            let text = strip_comment(text);
+            let tokens = tokenize(text, index + 1);
+            let mut rest = tokens.as_slice();
+            while let Some(token) = rest.first()
+                && let Some(name) = token.text.strip_suffix(':')
+            {
+                self.define(token, name, SymbolKind::Label)?;
+                rest = &rest[1..];
+            }
+            let Some((first, arguments)) = rest.split_first() else {
+                continue;
+            };
+            if first.text.starts_with('.') {
+                self.directive(first, arguments, text)?;
+            } else {
+                self.instruction(first, arguments)?;
+            }
+        }
+        self.finish()
+    }
+
+    fn define(
+        &mut self,
+        token: &Token<'_>,
+        text: &str,
+        kind: SymbolKind,
+    ) -> Result<(), AssembleError> {
+        let name = name(token, text)?;
+        let pc = self.ops.len();
+        if self.labels.insert(name.clone(), pc).is_some() {
+            return Err(token.error(AssembleErrorKind::DuplicateLabel(name)));
+        }
+        if kind == SymbolKind::Function || is_quoted(text) || !name.starts_with(LOCAL_PREFIX) {
+            self.program.add_symbol(name, kind, pc);
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        mnemonic: &Token<'_>,
+        arguments: &[Token<'_>],
+    ) -> Result<(), AssembleError> {
+        let code = OpCode::from_mnemonic(mnemonic.text).ok_or_else(|| {
+            mnemonic.error(AssembleErrorKind::UnknownMnemonic(
+                mnemonic.text.to_string(),
+            ))
+        })?;
+        let arg = match arguments {
+            [] if code.requires_argument() => {
+                return Err(mnemonic.error(AssembleErrorKind::MissingArgument));
+            }
+            [] => OpArg::None,
+            [argument] if is_quoted(argument.text) => {
+                self.fixups
+                    .push((self.ops.len(), Reference::new(argument)?));
+                OpArg::Instruction(0)
+            }
+            [argument] => match argument.text.parse::<OpArg>() {
+                Ok(arg) => arg,
+                Err(_) if is_identifier(argument.text) => {
+                    self.fixups
+                        .push((self.ops.len(), Reference::new(argument)?));
+                    OpArg::Instruction(0)
+                }
+                Err(_) if is_register(argument.text) => {
+                    return Err(argument.error(AssembleErrorKind::InvalidRegister(
+                        argument.text.to_string(),
+                    )));
+                }
+                Err(_) => {
+                    return Err(argument.error(AssembleErrorKind::InvalidArgument(
+                        argument.text.to_string(),
+                    )));
+                }
+            },
+            [_, extra, ..] => return Err(unexpected(extra)),
+        };
+
+        let pc = self.ops.len();
+        if self.lines.is_empty() && self.track_lines {
+            self.program
+                .add_line(pc, mnemonic.line as u32, mnemonic.column as u32);
+        }
+        self.attach_lines();
+        self.ops.push(Op::new(code, arg));
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        directive: &Token<'_>,
+        arguments: &[Token<'_>],
+        text: &str,
+    ) -> Result<(), AssembleError> {
+        match (directive.text, arguments) {
+            (".func", [name]) => self.define(name, name.text, SymbolKind::Function)?,
+            (".const", [value]) => {
+                self.program.add_constant(literal(value)?);
+            }
+            (".const", [symbol, value]) => {
+                let symbol = name(symbol, symbol.text)?;
+                let index = self.program.add_constant(literal(value)?);
+                self.program.add_symbol(symbol, SymbolKind::Constant, index);
+            }
+            (".global", [global, value]) => {
+                let global = name(global, global.text)?;
+                self.program.add_global(global, literal(value)?);
+            }
+            (".export", [export]) => {
+                let target = Target::Label(Reference::new(export)?);
+                self.exports.push((name(export, export.text)?, target));
+            }
+            (".export", [export, target]) => {
+                let target = match target.text.parse::<OpArg>() {
+                    Ok(OpArg::Instruction(address)) if !is_quoted(target.text) => {
+                        Target::Address(address as usize)
+                    }
+                    _ => Target::Label(Reference::new(target)?),
+                };
+                self.exports.push((name(export, export.text)?, target));
+            }
+            (".symbol", [kind, symbol, address]) => {
+                let kind = match kind.text {
+                    "function" => SymbolKind::Function,
+                    "label" => SymbolKind::Label,
+                    "constant" => SymbolKind::Constant,
+                    "global" => SymbolKind::Global,
+                    _ => {
+                        return Err(
+                            kind.error(AssembleErrorKind::InvalidArgument(kind.text.to_string()))
+                        );
+                    }
+                };
+                let symbol = name(symbol, symbol.text)?;
+                self.program.add_symbol(symbol, kind, number(address)?);
+            }
+            (".function", [function, entry, len, arity]) => {
+                let function = name(function, function.text)?;
+                self.program
+                    .add_function(function, number(entry)?, number(len)?, number(arity)?);
+            }
+            (".source", [path]) if is_quoted(path.text) => {
+                self.program.set_source(name(path, path.text)?);
+            }
+            (".source", [first, ..]) => {
+                self.program.set_source(text[first.column - 1..].trim_end());
+            }
+            (".line", [line]) => self.lines.push((number(line)?, 0)),
+            (".line", [line, column]) => self.lines.push((number(line)?, number(column)?)),
+            (
+                ".func" | ".const" | ".global" | ".export" | ".symbol" | ".function" | ".source"
+                | ".line",
+                [],
+            ) => {
+                return Err(directive.error(AssembleErrorKind::MissingArgument));
+            }
+            (".func", [_, extra, ..])
+            | (".const" | ".global" | ".export" | ".line", [_, _, extra, ..])
+            | (".symbol", [_, _, _, extra, ..])
+            | (".function", [_, _, _, _, extra, ..]) => {
+                return Err(unexpected(extra));
+            }
+            (".global", [_])
+            | (".symbol", [_] | [_, _])
+            | (".function", [_] | [_, _] | [_, _, _]) => {
+                return Err(directive.error(AssembleErrorKind::MissingArgument));
+            }
+            _ => {
+                return Err(directive.error(AssembleErrorKind::UnknownDirective(
+                    directive.text.to_string(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Program, AssembleError> {
+        for (pc, reference) in &self.fixups {
+            self.ops[*pc].arg = OpArg::Instruction(self.resolve(reference)? as u64);
+        }
+        for (name, target) in &self.exports {
+            let address = match target {
+                Target::Address(address) => *address,
+                Target::Label(reference) => self.resolve(reference)?,
+            };
+            self.program.add_export(name.as_str(), address);
+        }
+        self.attach_lines();
+        self.program.code = Cow::Owned(self.ops);
+        Ok(self.program)
+    }
+
+    fn attach_lines(&mut self) {
+        let pc = self.ops.len();
+        for (line, column) in self.lines.drain(..) {
+            self.program.add_line(pc, line, column);
+        }
+    }
+
+    fn resolve(&self, reference: &Reference) -> Result<usize, AssembleError> {
+        self.labels
+            .get(&reference.name)
+            .copied()
+            .ok_or_else(|| AssembleError {
+                line: reference.line,
+                column: reference.column,
+                kind: AssembleErrorKind::UndefinedLabel(reference.name.clone()),
+            })
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            COMMENT if !quoted => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn tokenize(text: &str, line: usize) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((begin, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut quoted = c == '"';
+        let mut end = text.len();
+        while let Some(&(index, c)) = chars.peek() {
+            if c.is_whitespace() && !quoted {
+                end = index;
+                break;
+            }
+            chars.next();
+            match c {
+                '\\' if quoted => {
+                    chars.next();
+                }
+                '"' => quoted = !quoted,
+                _ => {}
+            }
+        }
+        tokens.push(Token {
+            text: &text[begin..end],
+            line,
+            column: begin + 1,
+        });
+    }
+    tokens
+}
+
+fn name(token: &Token<'_>, text: &str) -> Result<String, AssembleError> {
+    let name = if is_quoted(text) {
+        unquote(text)
+    } else {
+        is_identifier(text).then(|| text.to_string())
+    };
+    name.ok_or_else(|| token.error(AssembleErrorKind::InvalidLabel(text.to_string())))
+}
+
+fn is_quoted(text: &str) -> bool {
+    text.starts_with('"')
+}
+
+fn unquote(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut name = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    let digits = chars.as_str().strip_prefix('{')?;
+                    let (digits, rest) = digits.split_once('}')?;
+                    chars = rest.chars();
+                    char::from_u32(u32::from_str_radix(digits, 16).ok()?)?
+                }
+                c @ ('\\' | '"' | '\'') => c,
+                _ => return None,
+            },
+            c => c,
+        };
+        name.push(c);
+    }
+    Some(name)
+}
+
+fn quote(name: &str) -> Cow<'_, str> {
+    if is_identifier(name) && !name.starts_with(LOCAL_PREFIX) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", name.escape_debug()))
+    }
+}
+
+fn quote_source(source: &str) -> Cow<'_, str> {
+    let plain = !source.is_empty()
+        && !source.starts_with('"')
+        && source.trim() == source
+        && !source.contains(|c: char| c == COMMENT || c.is_control());
+    if plain {
+        Cow::Borrowed(source)
+    } else {
+        Cow::Owned(format!("\"{}\"", source.escape_debug()))
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.');
+    valid_start
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && text.parse::<OpArg>().is_err()
+        && !is_register(text)
+}
+
+fn is_register(text: &str) -> bool {
+    text.strip_prefix('r')
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_label(kind: SymbolKind) -> bool {
+    matches!(kind, SymbolKind::Function | SymbolKind::Label)
+}
+
+fn literal(token: &Token<'_>) -> Result<MachineValue, AssembleError> {
+    let invalid = || token.error(AssembleErrorKind::InvalidArgument(token.text.to_string()));
+    match token.text.parse::<OpArg>().map_err(|_| invalid())? {
+        OpArg::Register1
+        | OpArg::Register2
+        | OpArg::Register3
+        | OpArg::Register4
+        | OpArg::Register5
+        | OpArg::Register6
+        | OpArg::Register7
+        | OpArg::Register8
+        | OpArg::Register9 => Err(invalid()),
+        arg => MachineValue::of(arg, &RegisterBank::new()).ok_or_else(invalid),
+    }
+}
+
+fn number<T: FromStr>(token: &Token<'_>) -> Result<T, AssembleError> {
+    token
+        .text
+        .parse()
+        .map_err(|_| token.error(AssembleErrorKind::InvalidArgument(token.text.to_string())))
+}
+
+fn unexpected(token: &Token<'_>) -> AssembleError {
+    token.error(AssembleErrorKind::UnexpectedToken(token.text.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{AssembleError, AssembleErrorKind};
+    use crate::machine::value::MachineValue;
+    use crate::op::OpArg::{Instruction, Register1, Uint64};
+    use crate::op::OpCode::{Add, Exit, Jump, JumpIfZero, Push, Return};
+    use crate::program::builder::ProgramBuilder;
+    use crate::program::link::Linker;
+    use crate::program::{Program, SymbolKind};
+    use crate::{op, program};
+
+    fn round_trip(program: &Program) {
+        let text = program.disassemble();
+        let assembled =
+            Program::assemble(&text).unwrap_or_else(|error| panic!("{}\n{}", error, text));
+        assert_eq!(assembled.encode(), program.encode(), "\n{}", text);
+    }
+
+    fn error(source: &str) -> AssembleError {
+        Program::assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn builder_programs_round_trip() {
+        let mut builder = ProgramBuilder::new();
+        let main = builder.function("my-func", 0);
+        let helper = builder.function("helper", 1);
+        let done = builder.named_label("done");
+        builder.named_constant("ten", MachineValue::Uint64(10));
+        builder.global("a b", MachineValue::Int64(-1));
+        builder.source("examples/my file.tvs");
+        builder.bind(main).line(1, 1).line(1, 5);
+        builder.push(Uint64(1)).call(helper).jump(done);
+        builder.bind(helper).line(4, 1);
+        builder.push(Uint64(2)).emit(Add).emit(Return);
+        builder.bind(done).emit(Exit).line(9, 1);
+        builder.export("my-func", main).export("entry", main);
+        round_trip(&builder.build().unwrap());
+    }
+
+    #[test]
+    fn linked_programs_round_trip() {
+        let mut library = ProgramBuilder::new();
+        let double = library.function("double", 1);
+        library.named_constant("two", MachineValue::Uint64(2));
+        library.global("calls", MachineValue::Uint64(0));
+        library.bind(double).line(1, 1);
+        library.push(Uint64(2)).emit(Add).emit(Return);
+        library.export("double", double);
+
+        let mut main = ProgramBuilder::new();
+        let entry = main.function("main", 0);
+        let double = main.import("double");
+        main.global("result", MachineValue::Uint64(0));
+        main.bind(entry).line(1, 1);
+        main.push(Uint64(3)).call(double).pop(Register1).emit(Exit);
+        main.export("main", entry);
+
+        let program = Linker::new()
+            .add(main.build_module("main").unwrap())
+            .add(library.build_module("library").unwrap())
+            .link()
+            .unwrap();
+        round_trip(&program);
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let mut program = program!(
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, Instruction(3)),
+            op!(Jump, Instruction(0)),
+            op!(Exit),
+        );
+        program.add_constant(MachineValue::Uint64(7));
+        program.add_constant(MachineValue::Int64(-7));
+        program.add_global("a b", MachineValue::Uint64(1));
+        program.add_global("r1", MachineValue::Uint64(2));
+        program.add_symbol(".Lskip", SymbolKind::Label, 3);
+        program.add_symbol(".L0", SymbolKind::Label, 0);
+        program.add_symbol("loop", SymbolKind::Label, 0);
+        program.add_symbol("loop", SymbolKind::Label, 2);
+        program.add_symbol("seven", SymbolKind::Constant, 0);
+        program.add_symbol("also seven", SymbolKind::Constant, 0);
+        program.add_symbol("missing", SymbolKind::Constant, 9);
+        program.add_symbol("a b", SymbolKind::Global, 0);
+        program.add_symbol("far", SymbolKind::Function, 40);
+        program.add_symbol("end", SymbolKind::Label, 4);
+        program.add_line(1, 3, 1);
+        program.add_line(1, 3, 9);
+        program.add_line(4, 10, 0);
+        program.add_function("loop", 0, 3, 2);
+        program.add_function("far", 40, 0, 0);
+        program.add_export("skip", 3);
+        program.add_export("beyond", 99);
+        program.set_source("main.tvs ; \"quoted\"");
+        round_trip(&program);
+    }
+
+    #[test]
+    fn quoted_names_assemble() {
+        let program = Program::assemble(
+            r#"
+            .global "x;y" 1u64
+            "my label":
+                jmp "my label"
+            .export "a\"b" "my label"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(program.globals()[0].name, "x;y");
+        assert_eq!(program.symbols()[0].name, "my label");
+        assert_eq!(program.exports()[0].name, "a\"b");
+        assert_eq!(program.get(0), Some(op!(Jump, Instruction(0))));
+    }
+
+    #[test]
+    fn errors_report_their_position() {
+        let cases = [
+            (
+                "push r10",
+                1,
+                6,
+                AssembleErrorKind::InvalidRegister("r10".into()),
+            ),
+            (
+                "  frob 1",
+                1,
+                3,
+                AssembleErrorKind::UnknownMnemonic("frob".into()),
+            ),
+            (
+                "jmp nowhere",
+                1,
+                5,
+                AssembleErrorKind::UndefinedLabel("nowhere".into()),
+            ),
+            (
+                "a:\na:",
+                2,
+                1,
+                AssembleErrorKind::DuplicateLabel("a".into()),
+            ),
+            (
+                ".const \"a\"b 1u64",
+                1,
+                8,
+                AssembleErrorKind::InvalidLabel("\"a\"b".into()),
+            ),
+            (
+                ".bogus",
+                1,
+                1,
+                AssembleErrorKind::UnknownDirective(".bogus".into()),
+            ),
+            (
+                "exit 1u64 2u64",
+                1,
+                11,
+                AssembleErrorKind::UnexpectedToken("2u64".into()),
+            ),
+        ];
+        for (source, line, column, kind) in cases {
+            let error = error(source);
+            assert_eq!(
+                (error.line, error.column, error.kind),
+                (line, column, kind),
+                "{}",
+                source
+            );
+        }
+    }
+}
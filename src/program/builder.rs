@@ -0,0 +1,405 @@
+use crate::error::BuildError;
+use crate::machine::value::MachineValue;
+use crate::op::{Op, OpArg, OpCode};
+use crate::program::link::{Module, RelocationTarget};
+use crate::program::{Program, SymbolKind};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static BUILDERS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub struct Label {
+    builder: usize,
+    index: usize,
+}
+
+#[derive(Clone, Debug)]
+struct LabelInfo {
+    name: Option<String>,
+    kind: SymbolKind,
+    address: Option<usize>,
+    import: bool,
+    arity: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProgramBuilder {
+    id: usize,
+    ops: Vec<Op>,
+    program: Program,
+    labels: Vec<LabelInfo>,
+    patches: Vec<(usize, Label)>,
+    exports: Vec<(String, Label)>,
+    error: Option<BuildError>,
+}
+
+impl Default for ProgramBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        Self {
+            id: BUILDERS.fetch_add(1, Ordering::Relaxed),
+            ops: Vec::new(),
+            program: Program::new(Vec::new()),
+            labels: Vec::new(),
+            patches: Vec::new(),
+            exports: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.declare(None, SymbolKind::Label, 0)
+    }
+
+    pub fn named_label(&mut self, name: impl Into<String>) -> Label {
+        self.declare(Some(name.into()), SymbolKind::Label, 0)
+    }
+
+    pub fn function(&mut self, name: impl Into<String>, arity: u32) -> Label {
+        self.declare(Some(name.into()), SymbolKind::Function, arity)
+    }
+
+    pub fn import(&mut self, name: impl Into<String>) -> Label {
+        let label = self.declare(Some(name.into()), SymbolKind::Function, 0);
+        self.labels[label.index].import = true;
+        label
+    }
+
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        let position = self.position();
+        let error = match self.labels.get_mut(label.index) {
+            _ if label.builder != self.id => BuildError::ForeignLabel,
+            Some(info) if info.import => BuildError::ImportBound(label_name(label, info)),
+            Some(info) if info.address.is_some() => {
+                BuildError::LabelRebound(label_name(label, info))
+            }
+            Some(info) => {
+                info.address = Some(position);
+                return self;
+            }
+            None => BuildError::ForeignLabel,
+        };
+        self.error.get_or_insert(error);
+        self
+    }
+
+    pub fn address(&self, label: Label) -> Option<usize> {
+        self.info(label)?.address
+    }
+
+    pub fn op(&mut self, op: Op) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn emit(&mut self, code: OpCode) -> &mut Self {
+        self.op(Op::new(code, OpArg::None))
+    }
+
+    pub fn emit_arg(&mut self, code: OpCode, arg: OpArg) -> &mut Self {
+        self.op(Op::new(code, arg))
+    }
+
+    pub fn emit_label(&mut self, code: OpCode, label: Label) -> &mut Self {
+        match self.info(label).map(|info| info.address) {
+            Some(Some(address)) => self.emit_arg(code, OpArg::Instruction(address as u64)),
+            Some(None) => {
+                self.patches.push((self.position(), label));
+                self.emit_arg(code, OpArg::Instruction(0))
+            }
+            None => {
+                self.error.get_or_insert(BuildError::ForeignLabel);
+                self.emit_arg(code, OpArg::Instruction(0))
+            }
+        }
+    }
+
+    pub fn push(&mut self, arg: OpArg) -> &mut Self {
+        self.emit_arg(OpCode::Push, arg)
+    }
+
+    pub fn pop(&mut self, register: OpArg) -> &mut Self {
+        self.emit_arg(OpCode::Pop, register)
+    }
+
+    pub fn jump(&mut self, label: Label) -> &mut Self {
+        self.emit_label(OpCode::Jump, label)
+    }
+
+    pub fn jump_if_zero(&mut self, label: Label) -> &mut Self {
+        self.emit_label(OpCode::JumpIfZero, label)
+    }
+
+    pub fn jump_if_equal(&mut self, label: Label) -> &mut Self {
+        self.emit_label(OpCode::JumpIfEqual, label)
+    }
+
+    pub fn call(&mut self, label: Label) -> &mut Self {
+        self.emit_label(OpCode::Call, label)
+    }
+
+    pub fn try_begin(&mut self, handler: Label) -> &mut Self {
+        self.emit_label(OpCode::TryBegin, handler)
+    }
+
+    pub fn constant(&mut self, value: MachineValue) -> usize {
+        self.program.add_constant(value)
+    }
+
+    pub fn named_constant(&mut self, name: impl Into<String>, value: MachineValue) -> usize {
+        let index = self.program.add_constant(value);
+        self.program.add_symbol(name, SymbolKind::Constant, index);
+        index
+    }
+
+    pub fn global(&mut self, name: impl Into<String>, value: MachineValue) -> usize {
+        self.program.add_global(name, value)
+    }
+
+    pub fn export(&mut self, name: impl Into<String>, label: Label) -> &mut Self {
+        if self.info(label).is_none() {
+            self.error.get_or_insert(BuildError::ForeignLabel);
+        } else {
+            self.exports.push((name.into(), label));
+        }
+        self
+    }
+
+    pub fn source(&mut self, source: impl Into<String>) -> &mut Self {
+        self.program.set_source(source);
+        self
+    }
+
+    pub fn line(&mut self, line: u32, column: u32) -> &mut Self {
+        self.program.add_line(self.position(), line, column);
+        self
+    }
+
+    pub fn build(self) -> Result<Program, BuildError> {
+        self.finish(false).map(|(program, _)| program)
+    }
+
+    pub fn build_module(self, name: impl Into<String>) -> Result<Module, BuildError> {
+        let (program, imports) = self.finish(true)?;
+        let mut module = Module::new(name, program);
+        for (pc, name) in imports {
+            let import = module.import(name);
+            module.relocate(pc, RelocationTarget::Import(import));
+        }
+        Ok(module)
+    }
+
+    fn finish(self, linkable: bool) -> Result<(Program, Vec<(usize, String)>), BuildError> {
+        let Self {
+            id: _,
+            mut ops,
+            mut program,
+            labels,
+            patches,
+            exports,
+            error,
+        } = self;
+        if let Some(error) = error {
+            return Err(error);
+        }
+        let resolve = |label: Label| {
+            let info = &labels[label.index];
+            info.address
+                .ok_or_else(|| BuildError::UnboundLabel(label_name(label, info)))
+        };
+
+        let mut imports = Vec::new();
+        for (pc, label) in patches {
+            let info = &labels[label.index];
+            match &info.name {
+                Some(name) if linkable && info.import => imports.push((pc, name.clone())),
+                _ => ops[pc].arg = OpArg::Instruction(resolve(label)? as u64),
+            }
+        }
+        for (name, label) in exports {
+            program.add_export(name, resolve(label)?);
+        }
+        for info in &labels {
+            if let (Some(name), Some(address)) = (&info.name, info.address) {
+                program.add_symbol(name.as_str(), info.kind, address);
+            }
+        }
+        let functions: Vec<_> = labels
+            .iter()
+            .filter(|info| info.kind == SymbolKind::Function)
+            .filter_map(|info| Some((info.name.as_deref()?, info.address?, info.arity)))
+            .collect();
+        for &(name, entry, arity) in &functions {
+            let end = functions
+                .iter()
+                .map(|&(_, address, _)| address)
+                .filter(|address| *address > entry)
+                .min()
+                .unwrap_or(ops.len());
+            program.add_function(name, entry, end - entry, arity);
+        }
+        program.code = Cow::Owned(ops);
+        Ok((program, imports))
+    }
+
+    fn declare(&mut self, name: Option<String>, kind: SymbolKind, arity: u32) -> Label {
+        if let Some(name) = &name
+            && self
+                .labels
+                .iter()
+                .any(|info| info.name.as_ref() == Some(name))
+        {
+            self.error
+                .get_or_insert(BuildError::DuplicateLabel(name.clone()));
+        }
+        self.labels.push(LabelInfo {
+            name,
+            kind,
+            address: None,
+            import: false,
+            arity,
+        });
+        Label {
+            builder: self.id,
+            index: self.labels.len() - 1,
+        }
+    }
+
+    fn info(&self, label: Label) -> Option<&LabelInfo> {
+        self.labels
+            .get(label.index)
+            .filter(|_| label.builder == self.id)
+    }
+}
+
+fn label_name(label: Label, info: &LabelInfo) -> String {
+    match &info.name {
+        Some(name) => name.clone(),
+        None => format!(".L{}", label.index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgramBuilder;
+    use crate::error::BuildError;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op;
+    use crate::op::OpArg::{Instruction, Register1, Uint64};
+    use crate::op::OpCode::{Add, Call, Exit, Jump, Return};
+    use crate::program::{Function, SymbolKind};
+
+    #[test]
+    fn forward_references_are_patched() {
+        let mut builder = ProgramBuilder::new();
+        let double = builder.function("double", 1);
+        let done = builder.named_label("done");
+        builder.push(Uint64(21)).call(double).jump(done);
+        builder
+            .bind(double)
+            .pop(Register1)
+            .push(Register1)
+            .push(Register1);
+        builder.emit(Add).emit(Return);
+        builder.bind(done).emit(Exit);
+        let program = builder.build().unwrap();
+
+        assert_eq!(program.get(1), Some(op!(Call, Instruction(3))));
+        assert_eq!(program.get(2), Some(op!(Jump, Instruction(8))));
+        assert_eq!(
+            program.symbol_at(SymbolKind::Label, 8).unwrap().name,
+            "done"
+        );
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+    }
+
+    #[test]
+    fn functions_record_entry_arity_and_extent() {
+        let mut builder = ProgramBuilder::new();
+        let main = builder.function("main", 0);
+        let helper = builder.function("helper", 2);
+        builder.bind(main).call(helper).emit(Exit);
+        builder.bind(helper).emit(Add).emit(Add).emit(Return);
+        let program = builder.build().unwrap();
+
+        let function = |name: &str, entry, len, arity| Function {
+            name: name.to_string(),
+            entry,
+            len,
+            arity,
+        };
+        assert_eq!(
+            program.functions(),
+            [function("main", 0, 2, 0), function("helper", 2, 3, 2)]
+        );
+        assert_eq!(program.function("helper").unwrap().arity, 2);
+    }
+
+    #[test]
+    fn misuse_is_reported_at_build_time() {
+        let mut builder = ProgramBuilder::new();
+        let label = builder.named_label("here");
+        builder.jump(label);
+        assert_eq!(
+            builder.build(),
+            Err(BuildError::UnboundLabel("here".to_string()))
+        );
+
+        let mut builder = ProgramBuilder::new();
+        let label = builder.label();
+        builder.bind(label).bind(label);
+        assert_eq!(
+            builder.build(),
+            Err(BuildError::LabelRebound(".L0".to_string()))
+        );
+
+        let mut builder = ProgramBuilder::new();
+        builder.named_label("twice");
+        builder.function("twice", 0);
+        assert_eq!(
+            builder.build(),
+            Err(BuildError::DuplicateLabel("twice".to_string()))
+        );
+
+        let mut builder = ProgramBuilder::new();
+        let import = builder.import("elsewhere");
+        builder.bind(import).emit(Exit);
+        assert_eq!(
+            builder.build(),
+            Err(BuildError::ImportBound("elsewhere".to_string()))
+        );
+    }
+
+    #[test]
+    fn labels_from_other_builders_are_rejected() {
+        let mut other = ProgramBuilder::new();
+        other.label();
+        other.label();
+        let foreign = other.label();
+
+        let mut builder = ProgramBuilder::new();
+        builder.jump(foreign).emit(Exit);
+        assert_eq!(builder.address(foreign), None);
+        assert_eq!(builder.build(), Err(BuildError::ForeignLabel));
+
+        let mut builder = ProgramBuilder::new();
+        builder.label();
+        builder.bind(foreign);
+        assert_eq!(builder.build(), Err(BuildError::ForeignLabel));
+
+        let mut builder = ProgramBuilder::new();
+        builder.export("main", foreign);
+        assert_eq!(builder.build(), Err(BuildError::ForeignLabel));
+    }
+}
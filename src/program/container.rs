@@ -0,0 +1,554 @@
+use crate::error::{DecodeError, DecodeReason, FormatError};
+use crate::machine::value::MachineValue;
+use crate::op::Op;
+use crate::program::{Program, SymbolKind};
+
+pub(super) const MAGIC: &[u8; 4] = b"TVMP";
+const VERSION: u16 = 1;
+pub(super) const FLAG_COMPACT: u16 = 1;
+pub(super) const FLAG_OBJECT: u16 = 2;
+const FLAGS: u16 = FLAG_COMPACT | FLAG_OBJECT;
+const HEADER_LEN: usize = 12;
+const ENTRY_LEN: usize = 10;
+
+const SECTION_CODE: u16 = 1;
+const SECTION_CONSTANTS: u16 = 2;
+const SECTION_GLOBALS: u16 = 3;
+const SECTION_SYMBOLS: u16 = 4;
+const SECTION_EXPORTS: u16 = 5;
+const SECTION_LINES: u16 = 6;
+const SECTION_FUNCTIONS: u16 = 10;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+pub(super) type Section<'bytes> = (u16, &'bytes [u8]);
+
+impl Program {
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_container(FLAG_COMPACT, self.encode_compact(), Vec::new())
+    }
+
+    pub fn encode_fixed(&self) -> Vec<u8> {
+        self.encode_container(0, self.encode_raw(), Vec::new())
+    }
+
+    pub(super) fn encode_container(
+        &self,
+        flags: u16,
+        code: Vec<u8>,
+        extra: Vec<(u16, Vec<u8>)>,
+    ) -> Vec<u8> {
+        let mut sections = vec![(SECTION_CODE, code)];
+        if !self.constants.is_empty() {
+            let mut data = Vec::new();
+            for value in &self.constants {
+                write_value(&mut data, *value);
+            }
+            sections.push((SECTION_CONSTANTS, data));
+        }
+        if !self.globals.is_empty() {
+            let mut data = Vec::new();
+            for global in &self.globals {
+                write_string(&mut data, &global.name);
+                write_value(&mut data, global.value);
+            }
+            sections.push((SECTION_GLOBALS, data));
+        }
+        if !self.symbols.is_empty() {
+            let mut data = Vec::new();
+            for symbol in &self.symbols {
+                write_string(&mut data, &symbol.name);
+                data.push(symbol.kind as u8);
+                data.extend_from_slice(&(symbol.address as u64).to_le_bytes());
+            }
+            sections.push((SECTION_SYMBOLS, data));
+        }
+        if !self.functions.is_empty() {
+            let mut data = Vec::new();
+            for function in &self.functions {
+                write_string(&mut data, &function.name);
+                data.extend_from_slice(&(function.entry as u64).to_le_bytes());
+                data.extend_from_slice(&(function.len as u64).to_le_bytes());
+                data.extend_from_slice(&function.arity.to_le_bytes());
+            }
+            sections.push((SECTION_FUNCTIONS, data));
+        }
+        if !self.exports.is_empty() {
+            let mut data = Vec::new();
+            for export in &self.exports {
+                write_string(&mut data, &export.name);
+                data.extend_from_slice(&(export.address as u64).to_le_bytes());
+            }
+            sections.push((SECTION_EXPORTS, data));
+        }
+        if self.source.is_some() || !self.lines.is_empty() {
+            let mut data = Vec::new();
+            write_string(&mut data, self.source.as_deref().unwrap_or_default());
+            for info in &self.lines {
+                data.extend_from_slice(&(info.pc as u64).to_le_bytes());
+                data.extend_from_slice(&info.line.to_le_bytes());
+                data.extend_from_slice(&info.column.to_le_bytes());
+            }
+            sections.push((SECTION_LINES, data));
+        }
+        sections.extend(extra);
+
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        buffer.extend_from_slice(&flags.to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        buffer.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+        let mut offset = buffer.len() + sections.len() * ENTRY_LEN;
+        for (tag, data) in &sections {
+            buffer.extend_from_slice(&tag.to_le_bytes());
+            buffer.extend_from_slice(&(offset as u32).to_le_bytes());
+            buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            offset += data.len();
+        }
+        for (_, data) in &sections {
+            buffer.extend_from_slice(data);
+        }
+        let checksum = crc32(&buffer[HEADER_LEN..]);
+        buffer[8..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+        buffer
+    }
+
+    fn decode_section(&mut self, tag: u16, reader: &mut Reader<'_>) -> Option<()> {
+        if tag == SECTION_LINES {
+            let source = reader.string()?;
+            self.source = (!source.is_empty()).then_some(source);
+        }
+        while !reader.0.is_empty() {
+            match tag {
+                SECTION_CONSTANTS => self.constants.push(reader.value()?),
+                SECTION_GLOBALS => {
+                    let name = reader.string()?;
+                    self.add_global(name, reader.value()?);
+                }
+                SECTION_SYMBOLS => {
+                    let name = reader.string()?;
+                    let kind = match reader.take(1)?[0] {
+                        0 => SymbolKind::Function,
+                        1 => SymbolKind::Label,
+                        2 => SymbolKind::Constant,
+                        3 => SymbolKind::Global,
+                        _ => return None,
+                    };
+                    self.add_symbol(name, kind, reader.usize()?);
+                }
+                SECTION_FUNCTIONS => {
+                    let name = reader.string()?;
+                    let entry = reader.usize()?;
+                    let len = reader.usize()?;
+                    self.add_function(name, entry, len, reader.u32()?);
+                }
+                SECTION_EXPORTS => {
+                    let name = reader.string()?;
+                    self.add_export(name, reader.usize()?);
+                }
+                SECTION_LINES => {
+                    let pc = reader.usize()?;
+                    let line = reader.u32()?;
+                    self.add_line(pc, line, reader.u32()?);
+                }
+                _ => return None,
+            }
+        }
+        Some(())
+    }
+
+    pub fn encode_compact(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for op in self.iter() {
+            op.encode_compact(&mut buffer);
+        }
+        buffer
+    }
+
+    pub fn encode_raw(&self) -> Vec<u8> {
+        let mut buffer = vec![0; self.len() * Op::encoded_len()];
+        for (i, op) in self.iter().enumerate() {
+            op.encode(&mut buffer[i * Op::encoded_len()..]);
+        }
+        buffer
+    }
+}
+
+impl Program {
+    pub fn decode(buffer: &[u8]) -> Result<Program, FormatError> {
+        let (flags, sections) = sections(buffer)?;
+        if flags & FLAG_OBJECT != 0 {
+            return Err(FormatError::Flags(flags));
+        }
+        Program::from_sections(flags, &sections)
+    }
+
+    pub fn decode_compact(buffer: &[u8]) -> Result<Program, FormatError> {
+        let mut ops = Vec::new();
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let (op, length) = Op::decode_compact(&buffer[offset..])
+                .map_err(|error| error.at(offset, ops.len()))?;
+            ops.push(op);
+            offset += length;
+        }
+        Ok(Self::new(ops))
+    }
+
+    pub fn decode_raw(buffer: &[u8]) -> Result<Program, FormatError> {
+        let chunks = buffer.chunks_exact(Op::encoded_len());
+        let remainder = chunks.remainder().len();
+        let ops = chunks
+            .enumerate()
+            .map(|(i, chunk)| Op::decode(chunk).map_err(|error| error.at(i * Op::encoded_len(), i)))
+            .collect::<Result<Vec<Op>, DecodeError>>()?;
+        trailing(buffer.len(), remainder)?;
+        Ok(Self::new(ops))
+    }
+
+    pub(super) fn from_sections(
+        flags: u16,
+        sections: &[Section<'_>],
+    ) -> Result<Program, FormatError> {
+        let code = code_section(sections)?;
+        let mut program = if flags & FLAG_COMPACT != 0 {
+            Program::decode_compact(code)?
+        } else {
+            Program::decode_raw(code)?
+        };
+        program.decode_metadata(sections)?;
+        Ok(program)
+    }
+
+    pub(super) fn decode_metadata(&mut self, sections: &[Section<'_>]) -> Result<(), FormatError> {
+        for (tag, data) in sections {
+            if (SECTION_CONSTANTS..=SECTION_LINES).contains(tag) || *tag == SECTION_FUNCTIONS {
+                self.decode_section(*tag, &mut Reader(data))
+                    .ok_or(FormatError::SectionInvalid(*tag))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn code_section<'bytes>(
+    sections: &[Section<'bytes>],
+) -> Result<&'bytes [u8], FormatError> {
+    sections
+        .iter()
+        .find(|(tag, _)| *tag == SECTION_CODE)
+        .map(|(_, data)| *data)
+        .ok_or(FormatError::MissingSection(SECTION_CODE))
+}
+
+pub(super) fn sections(buffer: &[u8]) -> Result<(u16, Vec<Section<'_>>), FormatError> {
+    if buffer.len() < MAGIC.len() || &buffer[..MAGIC.len()] != MAGIC {
+        return Err(FormatError::Magic);
+    }
+    if buffer.len() < HEADER_LEN + 2 {
+        return Err(FormatError::Truncated);
+    }
+    let version = u16::from_le_bytes([buffer[4], buffer[5]]);
+    if version != VERSION {
+        return Err(FormatError::Version(version));
+    }
+    let flags = u16::from_le_bytes([buffer[6], buffer[7]]);
+    if flags & !FLAGS != 0 {
+        return Err(FormatError::Flags(flags));
+    }
+    let expected = u32::from_le_bytes(buffer[8..HEADER_LEN].try_into().unwrap());
+    let actual = crc32(&buffer[HEADER_LEN..]);
+    if expected != actual {
+        return Err(FormatError::Checksum { expected, actual });
+    }
+
+    let count = u16::from_le_bytes([buffer[HEADER_LEN], buffer[HEADER_LEN + 1]]) as usize;
+    let directory = &buffer[HEADER_LEN + 2..];
+    if directory.len() < count * ENTRY_LEN {
+        return Err(FormatError::Truncated);
+    }
+    let mut sections = Vec::new();
+    for entry in directory[..count * ENTRY_LEN].chunks_exact(ENTRY_LEN) {
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let offset = u32::from_le_bytes(entry[2..6].try_into().unwrap()) as usize;
+        let length = u32::from_le_bytes(entry[6..10].try_into().unwrap()) as usize;
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or(FormatError::Section(tag))?;
+        sections.push((tag, data));
+    }
+    Ok((flags, sections))
+}
+
+pub(super) fn validate(buffer: &[u8]) -> Result<(), DecodeError> {
+    let chunks = buffer.chunks_exact(Op::encoded_len());
+    let remainder = chunks.remainder().len();
+    for (i, chunk) in chunks.enumerate() {
+        Op::decode(chunk).map_err(|error| error.at(i * Op::encoded_len(), i))?;
+    }
+    trailing(buffer.len(), remainder)
+}
+
+fn trailing(len: usize, remainder: usize) -> Result<(), DecodeError> {
+    if remainder == 0 {
+        return Ok(());
+    }
+    let offset = len - remainder;
+    Err(DecodeError::new(0, DecodeReason::TrailingBytes(remainder))
+        .at(offset, offset / Op::encoded_len()))
+}
+
+pub(super) fn index_compact(buffer: &[u8]) -> Result<Vec<u32>, DecodeError> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let (_, length) = Op::decode_compact(&buffer[offset..])
+            .map_err(|error| error.at(offset, offsets.len()))?;
+        offsets.push(offset as u32);
+        offset += length;
+    }
+    Ok(offsets)
+}
+
+pub(super) struct Reader<'buffer>(pub(super) &'buffer [u8]);
+
+impl<'buffer> Reader<'buffer> {
+    pub(super) fn take(&mut self, length: usize) -> Option<&'buffer [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(length);
+        self.0 = tail;
+        Some(head)
+    }
+
+    pub(super) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(super) fn usize(&mut self) -> Option<usize> {
+        usize::try_from(u64::from_le_bytes(self.take(8)?.try_into().ok()?)).ok()
+    }
+
+    pub(super) fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).ok()
+    }
+
+    fn value(&mut self) -> Option<MachineValue> {
+        MachineValue::decode(self.take(MachineValue::encoded_len())?)
+    }
+}
+
+pub(super) fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn write_value(buffer: &mut Vec<u8>, value: MachineValue) {
+    let mut encoded = [0; MachineValue::encoded_len()];
+    value.encode(&mut encoded);
+    buffer.extend_from_slice(&encoded);
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub(crate) fn crc32(buffer: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in buffer {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FLAG_COMPACT, HEADER_LEN, SECTION_CODE, SECTION_SYMBOLS, crc32};
+    use crate::error::{DecodeReason, FormatError};
+    use crate::machine::value::MachineValue;
+    use crate::op::OpArg::{Instruction, Uint64};
+    use crate::op::OpCode::{Exit, JumpIfZero, Push};
+    use crate::program::view::ProgramView;
+    use crate::program::{Program, SymbolKind};
+    use crate::{op, program};
+
+    fn sample() -> Program {
+        let mut program = program!(
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, Instruction(3)),
+            op!(Push, Uint64(300)),
+            op!(Exit),
+        );
+        program.add_constant(MachineValue::Int32(-5));
+        program.add_global("counter", MachineValue::Uint64(7));
+        program.add_symbol("main", SymbolKind::Function, 0);
+        program.add_symbol("done", SymbolKind::Label, 3);
+        program.add_function("main", 0, 4, 0);
+        program.add_export("main", 0);
+        program.set_source("sample.tvs");
+        program.add_line(0, 1, 1);
+        program.add_line(3, 4, 5);
+        program
+    }
+
+    fn reseal(buffer: &mut [u8]) {
+        let checksum = crc32(&buffer[HEADER_LEN..]);
+        buffer[8..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn containers_round_trip_with_every_section() {
+        let program = sample();
+        assert_eq!(Program::decode(&program.encode()), Ok(program.clone()));
+        assert_eq!(
+            Program::decode(&program.encode_fixed()),
+            Ok(program.clone())
+        );
+        assert!(program.encode().len() < program.encode_fixed().len());
+
+        let empty = Program::new(Vec::new());
+        assert_eq!(Program::decode(&empty.encode()), Ok(empty));
+    }
+
+    #[test]
+    fn corrupted_containers_fail_the_checksum() {
+        let buffer = sample().encode();
+        for index in HEADER_LEN..buffer.len() {
+            let mut corrupted = buffer.clone();
+            corrupted[index] ^= 0x10;
+            assert!(
+                matches!(
+                    Program::decode(&corrupted),
+                    Err(FormatError::Checksum { .. })
+                ),
+                "byte {} was not covered",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let buffer = sample().encode();
+        assert_eq!(Program::decode(b"TVM"), Err(FormatError::Magic));
+        assert_eq!(Program::decode(b"NOPE0000000000"), Err(FormatError::Magic));
+        assert_eq!(Program::decode(&buffer[..10]), Err(FormatError::Truncated));
+
+        let mut version = buffer.clone();
+        version[4] = 9;
+        assert_eq!(Program::decode(&version), Err(FormatError::Version(9)));
+
+        let mut flags = buffer.clone();
+        flags[7] = 1;
+        assert_eq!(
+            Program::decode(&flags),
+            Err(FormatError::Flags(0x100 | FLAG_COMPACT))
+        );
+
+        let mut count = buffer.clone();
+        count[HEADER_LEN] = 0xff;
+        reseal(&mut count);
+        assert_eq!(Program::decode(&count), Err(FormatError::Truncated));
+    }
+
+    #[test]
+    fn malformed_sections_are_rejected() {
+        let program = sample();
+        let buffer = program.encode();
+        let directory = HEADER_LEN + 2;
+
+        let mut length = buffer.clone();
+        length[directory + 6..directory + 10].copy_from_slice(&u32::MAX.to_le_bytes());
+        reseal(&mut length);
+        assert_eq!(
+            Program::decode(&length),
+            Err(FormatError::Section(SECTION_CODE))
+        );
+
+        let mut missing = buffer.clone();
+        missing[directory] = 99;
+        reseal(&mut missing);
+        assert_eq!(
+            Program::decode(&missing),
+            Err(FormatError::MissingSection(SECTION_CODE))
+        );
+
+        let invalid = program.encode_container(
+            FLAG_COMPACT,
+            program.encode_compact(),
+            vec![(SECTION_SYMBOLS, vec![0xff])],
+        );
+        assert_eq!(
+            Program::decode(&invalid),
+            Err(FormatError::SectionInvalid(SECTION_SYMBOLS))
+        );
+
+        let mut code = program.encode_fixed();
+        let offset = u32::from_le_bytes(code[directory + 2..directory + 6].try_into().unwrap());
+        code[offset as usize + 20] = 0xff;
+        reseal(&mut code);
+        let Err(FormatError::Decode(error)) = Program::decode(&code) else {
+            panic!("expected a decode error");
+        };
+        assert_eq!((error.offset, error.index), (20, 2));
+        assert_eq!(error.reason, DecodeReason::UnknownOpCode(0xff));
+    }
+
+    #[test]
+    fn raw_streams_report_trailing_bytes() {
+        let program = sample();
+        let mut buffer = program.encode_raw();
+        assert_eq!(
+            Program::decode_raw(&buffer),
+            Ok(Program::new(program.ops().to_vec()))
+        );
+        buffer.extend_from_slice(&[0, 13, 1]);
+        for result in [
+            Program::decode_raw(&buffer).map(|_| ()),
+            ProgramView::new(&buffer).map(|_| ()),
+        ] {
+            let Err(FormatError::Decode(error)) = result else {
+                panic!("expected a decode error");
+            };
+            assert_eq!((error.offset, error.index), (40, 4));
+            assert_eq!(error.reason, DecodeReason::TrailingBytes(3));
+        }
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped() {
+        let program = sample();
+        let buffer = program.encode_container(
+            FLAG_COMPACT,
+            program.encode_compact(),
+            vec![(99, vec![1, 2, 3])],
+        );
+        assert_eq!(Program::decode(&buffer), Ok(program));
+    }
+}
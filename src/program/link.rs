@@ -0,0 +1,504 @@
+use crate::error::{FormatError, LinkError};
+use crate::op::OpArg;
+use crate::program::container::{FLAG_COMPACT, FLAG_OBJECT, Reader, sections, write_string};
+use crate::program::{Program, SymbolKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+const SECTION_MODULE: u16 = 7;
+const SECTION_IMPORTS: u16 = 8;
+const SECTION_RELOCATIONS: u16 = 9;
+
+const RELOCATION_LOCAL: u8 = 0;
+const RELOCATION_IMPORT: u8 = 1;
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub enum RelocationTarget {
+    Local,
+    Import(usize),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub struct Relocation {
+    pub pc: usize,
+    pub target: RelocationTarget,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Module {
+    name: String,
+    program: Program,
+    imports: Vec<String>,
+    relocations: Vec<Relocation>,
+}
+
+impl Module {
+    pub fn new(name: impl Into<String>, program: Program) -> Module {
+        let relocations = program
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| matches!(op.arg, OpArg::Instruction(_)))
+            .map(|(pc, _)| Relocation {
+                pc,
+                target: RelocationTarget::Local,
+            })
+            .collect();
+        Self {
+            name: name.into(),
+            program,
+            imports: Vec::new(),
+            relocations,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn imports(&self) -> &[String] {
+        &self.imports
+    }
+
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations
+    }
+
+    fn qualify<'name>(&self, name: &'name str, kind: SymbolKind) -> Cow<'name, str> {
+        let exported = self
+            .program
+            .exports
+            .iter()
+            .any(|export| export.name == name);
+        match kind {
+            SymbolKind::Function | SymbolKind::Label if exported => Cow::Borrowed(name),
+            SymbolKind::Global => Cow::Borrowed(name),
+            _ => Cow::Owned(format!("{}::{}", self.name, name)),
+        }
+    }
+
+    fn names(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let exports = self.program.exports.iter().map(|export| &export.name);
+        let globals = self.program.globals.iter().map(|global| &global.name);
+        let symbols = self
+            .program
+            .symbols
+            .iter()
+            .map(|symbol| self.qualify(&symbol.name, symbol.kind));
+        exports
+            .chain(globals)
+            .map(|name| Cow::Borrowed(name.as_str()))
+            .chain(symbols)
+    }
+
+    pub fn import(&mut self, name: impl Into<String>) -> usize {
+        let name = name.into();
+        match self.imports.iter().position(|import| *import == name) {
+            Some(index) => index,
+            None => {
+                self.imports.push(name);
+                self.imports.len() - 1
+            }
+        }
+    }
+
+    pub fn relocate(&mut self, pc: usize, target: RelocationTarget) {
+        let relocation = Relocation { pc, target };
+        match self
+            .relocations
+            .binary_search_by_key(&pc, |relocation| relocation.pc)
+        {
+            Ok(index) => self.relocations[index] = relocation,
+            Err(index) => self.relocations.insert(index, relocation),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut module = Vec::new();
+        write_string(&mut module, &self.name);
+        let mut imports = Vec::new();
+        for import in &self.imports {
+            write_string(&mut imports, import);
+        }
+        let mut relocations = Vec::new();
+        for relocation in &self.relocations {
+            relocations.extend_from_slice(&(relocation.pc as u64).to_le_bytes());
+            let (kind, import) = match relocation.target {
+                RelocationTarget::Local => (RELOCATION_LOCAL, 0),
+                RelocationTarget::Import(import) => (RELOCATION_IMPORT, import as u32),
+            };
+            relocations.push(kind);
+            relocations.extend_from_slice(&import.to_le_bytes());
+        }
+        self.program.encode_container(
+            FLAG_OBJECT | FLAG_COMPACT,
+            self.program.encode_compact(),
+            vec![
+                (SECTION_MODULE, module),
+                (SECTION_IMPORTS, imports),
+                (SECTION_RELOCATIONS, relocations),
+            ],
+        )
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Module, FormatError> {
+        let (flags, sections) = sections(buffer)?;
+        if flags & FLAG_OBJECT == 0 {
+            return Err(FormatError::Flags(flags));
+        }
+        let program = Program::from_sections(flags, &sections)?;
+        let section = |tag: u16| {
+            sections
+                .iter()
+                .find(|(section, _)| *section == tag)
+                .map(|(_, data)| Reader(data))
+                .ok_or(FormatError::MissingSection(tag))
+        };
+
+        let mut reader = section(SECTION_MODULE)?;
+        let name = reader
+            .string()
+            .ok_or(FormatError::SectionInvalid(SECTION_MODULE))?;
+        let mut module = Module {
+            name,
+            program,
+            imports: Vec::new(),
+            relocations: Vec::new(),
+        };
+
+        let mut reader = section(SECTION_IMPORTS)?;
+        while !reader.0.is_empty() {
+            let import = reader
+                .string()
+                .ok_or(FormatError::SectionInvalid(SECTION_IMPORTS))?;
+            module.imports.push(import);
+        }
+
+        let mut reader = section(SECTION_RELOCATIONS)?;
+        while !reader.0.is_empty() {
+            let relocation = reader
+                .usize()
+                .zip(reader.take(1))
+                .zip(reader.u32())
+                .and_then(|((pc, kind), import)| {
+                    let target = match kind[0] {
+                        RELOCATION_LOCAL => RelocationTarget::Local,
+                        RELOCATION_IMPORT => RelocationTarget::Import(import as usize),
+                        _ => return None,
+                    };
+                    Some(Relocation { pc, target })
+                })
+                .ok_or(FormatError::SectionInvalid(SECTION_RELOCATIONS))?;
+            module.relocations.push(relocation);
+        }
+        Ok(module)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Linker {
+    modules: Vec<Module>,
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Self::default()
+    }
+
+    pub fn add(&mut self, module: Module) -> &mut Self {
+        self.modules.push(module);
+        self
+    }
+
+    pub fn link(&self) -> Result<Program, LinkError> {
+        let mut bases = Vec::with_capacity(self.modules.len());
+        let mut exports: HashMap<&str, (usize, usize)> = HashMap::new();
+        let mut names: HashMap<Cow<'_, str>, usize> = HashMap::new();
+        let mut base = 0;
+        for (index, module) in self.modules.iter().enumerate() {
+            bases.push(base);
+            for export in module.program.exports() {
+                if let Some((first, _)) =
+                    exports.insert(&export.name, (index, base + export.address))
+                {
+                    return Err(LinkError::DuplicateSymbol {
+                        name: export.name.clone(),
+                        first: self.modules[first].name.clone(),
+                        second: module.name.clone(),
+                    });
+                }
+            }
+            for name in module.names() {
+                match names.get(&name) {
+                    Some(&first) if first != index => {
+                        return Err(LinkError::DuplicateSymbol {
+                            name: name.into_owned(),
+                            first: self.modules[first].name.clone(),
+                            second: module.name.clone(),
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        names.insert(name, index);
+                    }
+                }
+            }
+            base += module.program.len();
+        }
+
+        let mut ops = Vec::with_capacity(base);
+        let mut program = Program::new(Vec::new());
+        for (module, base) in self.modules.iter().zip(bases) {
+            let start = ops.len();
+            ops.extend(module.program.iter());
+            for relocation in &module.relocations {
+                let invalid = || LinkError::InvalidRelocation {
+                    module: module.name.clone(),
+                    pc: relocation.pc,
+                };
+                let op = ops.get_mut(start + relocation.pc).ok_or_else(invalid)?;
+                let OpArg::Instruction(target) = op.arg else {
+                    return Err(invalid());
+                };
+                let address = match relocation.target {
+                    RelocationTarget::Local => target as usize + base,
+                    RelocationTarget::Import(import) => {
+                        let name = module.imports.get(import).ok_or_else(invalid)?;
+                        match exports.get(name.as_str()) {
+                            Some((_, address)) => *address,
+                            None => {
+                                return Err(LinkError::UndefinedSymbol {
+                                    name: name.clone(),
+                                    module: module.name.clone(),
+                                });
+                            }
+                        }
+                    }
+                };
+                op.arg = OpArg::Instruction(address as u64);
+            }
+            program.merge(module, base);
+        }
+        program.code = Cow::Owned(ops);
+        Ok(program)
+    }
+}
+
+impl Program {
+    fn merge(&mut self, module: &Module, base: usize) {
+        let other = &module.program;
+        let constants = self.constants.len();
+        let globals = self.globals.len();
+        self.constants.extend_from_slice(&other.constants);
+        self.globals.extend_from_slice(&other.globals);
+        for symbol in &other.symbols {
+            let address = match symbol.kind {
+                SymbolKind::Function | SymbolKind::Label => symbol.address + base,
+                SymbolKind::Constant => symbol.address + constants,
+                SymbolKind::Global => symbol.address + globals,
+            };
+            self.add_symbol(
+                module.qualify(&symbol.name, symbol.kind),
+                symbol.kind,
+                address,
+            );
+        }
+        for function in &other.functions {
+            self.add_function(
+                module.qualify(&function.name, SymbolKind::Function),
+                function.entry + base,
+                function.len,
+                function.arity,
+            );
+        }
+        for export in &other.exports {
+            self.add_export(export.name.as_str(), export.address + base);
+        }
+        for info in &other.lines {
+            self.add_line(info.pc + base, info.line, info.column);
+        }
+        if self.source.is_none() {
+            self.source = other.source.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Linker, Module, RelocationTarget};
+    use crate::error::LinkError;
+    use crate::machine::value::MachineValue;
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op;
+    use crate::op::OpArg::{Instruction, Register1, Uint64};
+    use crate::op::OpCode::{Add, Call, Exit, Jump, Return};
+    use crate::program::builder::ProgramBuilder;
+    use crate::program::{Program, SymbolKind};
+
+    fn app() -> Module {
+        let mut builder = ProgramBuilder::new();
+        let main = builder.function("main", 0);
+        let quadruple = builder.import("quadruple");
+        builder.global("result", MachineValue::Uint64(0));
+        builder
+            .bind(main)
+            .push(Uint64(3))
+            .call(quadruple)
+            .emit(Exit);
+        builder.export("main", main);
+        builder.build_module("app").unwrap()
+    }
+
+    fn library(name: &str, export: &str, body: impl FnOnce(&mut ProgramBuilder)) -> Module {
+        let mut builder = ProgramBuilder::new();
+        let entry = builder.function(export, 1);
+        let done = builder.named_label("done");
+        builder.named_constant("one", MachineValue::Uint64(1));
+        builder.bind(entry);
+        body(&mut builder);
+        builder.jump(done).bind(done).emit(Return);
+        builder.export(export, entry);
+        builder.build_module(name).unwrap()
+    }
+
+    fn double() -> Module {
+        library("double", "double", |builder| {
+            builder
+                .pop(Register1)
+                .push(Register1)
+                .push(Register1)
+                .emit(Add);
+        })
+    }
+
+    fn quadruple() -> Module {
+        library("quadruple", "quadruple", |builder| {
+            let double = builder.import("double");
+            builder.call(double).call(double);
+        })
+    }
+
+    fn link(modules: impl IntoIterator<Item = Module>) -> Result<Program, LinkError> {
+        let mut linker = Linker::new();
+        for module in modules {
+            linker.add(module);
+        }
+        linker.link()
+    }
+
+    #[test]
+    fn modules_link_and_run() {
+        let program = link([app(), quadruple(), double()]).unwrap();
+        assert_eq!(program.get(1), Some(op!(Call, Instruction(3))));
+        assert_eq!(program.get(3), Some(op!(Call, Instruction(7))));
+        assert_eq!(program.get(5), Some(op!(Jump, Instruction(6))));
+        assert_eq!(program.export("double"), Some(7));
+
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(machine.stack(), [MachineValue::Uint64(12)]);
+    }
+
+    #[test]
+    fn local_symbols_are_qualified_by_module() {
+        let program = link([app(), quadruple(), double()]).unwrap();
+        let names: Vec<_> = program
+            .symbols()
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.address))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("main", SymbolKind::Function, 0),
+                ("quadruple", SymbolKind::Function, 3),
+                ("double", SymbolKind::Function, 7),
+                ("quadruple::done", SymbolKind::Label, 6),
+                ("double::done", SymbolKind::Label, 12),
+                ("quadruple::one", SymbolKind::Constant, 0),
+                ("double::one", SymbolKind::Constant, 1),
+            ]
+        );
+        let functions: Vec<_> = program
+            .functions()
+            .iter()
+            .map(|function| (function.name.as_str(), function.entry, function.len))
+            .collect();
+        assert_eq!(
+            functions,
+            [("main", 0, 3), ("quadruple", 3, 4), ("double", 7, 6)]
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let duplicate = |name: &str, first: &str, second: &str| {
+            Err(LinkError::DuplicateSymbol {
+                name: name.to_string(),
+                first: first.to_string(),
+                second: second.to_string(),
+            })
+        };
+        assert_eq!(
+            link([app(), double(), double()]),
+            duplicate("double", "double", "double")
+        );
+
+        let mut builder = ProgramBuilder::new();
+        builder.global("result", MachineValue::Uint64(1));
+        let globals = builder.build_module("globals").unwrap();
+        assert_eq!(
+            link([app(), quadruple(), double(), globals]),
+            duplicate("result", "app", "globals")
+        );
+
+        let mut builder = ProgramBuilder::new();
+        builder.global("double", MachineValue::Uint64(1));
+        let shadow = builder.build_module("shadow").unwrap();
+        assert_eq!(
+            link([app(), quadruple(), double(), shadow]),
+            duplicate("double", "double", "shadow")
+        );
+
+        let mut builder = ProgramBuilder::new();
+        builder.named_constant("one", MachineValue::Uint64(1));
+        let renamed = builder.build_module("double").unwrap();
+        assert_eq!(
+            link([app(), quadruple(), double(), renamed]),
+            duplicate("double::one", "double", "double")
+        );
+    }
+
+    #[test]
+    fn undefined_imports_and_bad_relocations_are_rejected() {
+        assert_eq!(
+            link([app(), quadruple()]),
+            Err(LinkError::UndefinedSymbol {
+                name: "double".to_string(),
+                module: "quadruple".to_string(),
+            })
+        );
+
+        let mut module = double();
+        module.relocate(0, RelocationTarget::Local);
+        assert_eq!(
+            link([module]),
+            Err(LinkError::InvalidRelocation {
+                module: "double".to_string(),
+                pc: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn modules_round_trip_through_the_container() {
+        for module in [app(), quadruple(), double()] {
+            let decoded = Module::decode(&module.encode()).unwrap();
+            assert_eq!(decoded, module);
+        }
+        assert!(Program::decode(&app().encode()).is_err());
+    }
+}
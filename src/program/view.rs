@@ -0,0 +1,215 @@
+use crate::error::FormatError;
+use crate::op::Op;
+use crate::program::container::{
+    FLAG_COMPACT, FLAG_OBJECT, MAGIC, code_section, index_compact, sections, validate,
+};
+use crate::program::{Program, fingerprint};
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    Fixed,
+    Compact(Vec<u32>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramView<'bytes> {
+    program: Program,
+    code: &'bytes [u8],
+    encoding: Encoding,
+}
+
+impl<'bytes> ProgramView<'bytes> {
+    pub fn new(buffer: &'bytes [u8]) -> Result<ProgramView<'bytes>, FormatError> {
+        if !buffer.starts_with(MAGIC) {
+            validate(buffer)?;
+            return Ok(Self {
+                program: Program::new(Vec::new()),
+                code: buffer,
+                encoding: Encoding::Fixed,
+            });
+        }
+
+        let (flags, sections) = sections(buffer)?;
+        if flags & FLAG_OBJECT != 0 {
+            return Err(FormatError::Flags(flags));
+        }
+        let code = code_section(&sections)?;
+        let encoding = if flags & FLAG_COMPACT != 0 {
+            Encoding::Compact(index_compact(code)?)
+        } else {
+            validate(code)?;
+            Encoding::Fixed
+        };
+        let mut program = Program::new(Vec::new());
+        program.decode_metadata(&sections)?;
+        Ok(Self {
+            program,
+            code,
+            encoding,
+        })
+    }
+
+    #[inline(always)]
+    pub fn get(&self, pc: usize) -> Option<Op> {
+        match &self.encoding {
+            Encoding::Fixed => {
+                let start = pc.checked_mul(Op::encoded_len())?;
+                let op = self.code.get(start..start + Op::encoded_len())?;
+                Op::decode(op).ok()
+            }
+            Encoding::Compact(offsets) => {
+                let start = *offsets.get(pc)? as usize;
+                Op::decode_compact(&self.code[start..])
+                    .ok()
+                    .map(|(op, _)| op)
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Fixed => self.code.len() / Op::encoded_len(),
+            Encoding::Compact(offsets) => offsets.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Op> + '_ {
+        (0..self.len()).filter_map(|pc| self.get(pc))
+    }
+
+    pub fn is_compact(&self) -> bool {
+        matches!(self.encoding, Encoding::Compact(_))
+    }
+
+    pub fn metadata(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn to_program(&self) -> Program {
+        let mut program = self.program.clone();
+        program.code = Cow::Owned(self.iter().collect());
+        program
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        fingerprint(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgramView;
+    use crate::error::{DecodeReason, FormatError};
+    use crate::machine::value::MachineValue;
+    use crate::machine::watch::{WatchCondition, Watchpoint};
+    use crate::machine::{Machine, MachineLoopState};
+    use crate::op::OpArg::{Register1, Uint64};
+    use crate::op::OpCode::{Add, Exit, JumpIfZero, Pop, Push, Subtract};
+    use crate::program::Program;
+    use crate::{op, program};
+
+    fn countdown() -> Program {
+        let mut program = program!(
+            op!(Push, Uint64(0)),
+            op!(Pop, Register1),
+            op!(Push, Uint64(5)),
+            start:
+            op!(Push, Uint64(1)),
+            op!(Subtract),
+            op!(Push, Register1),
+            op!(Push, Uint64(1)),
+            op!(Add),
+            op!(Pop, Register1),
+            op!(Pop, Register1),
+            op!(Push, Register1),
+            op!(Push, Register1),
+            op!(JumpIfZero, done),
+            op!(Push, Uint64(0)),
+            op!(JumpIfZero, start),
+            done:
+            op!(Exit),
+        );
+        program.add_global("limit", MachineValue::Uint64(5));
+        program.add_export("main", 0);
+        program
+    }
+
+    #[test]
+    fn views_decode_every_encoding_on_demand() {
+        let program = countdown();
+        let encodings = [
+            program.encode(),
+            program.encode_fixed(),
+            program.encode_raw(),
+        ];
+        for (buffer, compact) in encodings.iter().zip([true, false, false]) {
+            let view = ProgramView::new(buffer).unwrap();
+            assert_eq!(view.is_compact(), compact);
+            assert_eq!(view.len(), program.len());
+            assert!(view.iter().eq(program.iter()));
+            assert_eq!(view.get(program.len()), None);
+            assert_eq!(view.fingerprint(), program.fingerprint());
+        }
+
+        let view = ProgramView::new(&encodings[0]).unwrap();
+        assert!(view.metadata().is_empty());
+        assert_eq!(view.metadata().globals(), program.globals());
+        assert_eq!(view.to_program(), program);
+    }
+
+    #[test]
+    fn machines_run_from_views() {
+        let program = countdown();
+        let mut owned = Machine::new(&program);
+        assert_eq!(owned.run(), Ok(MachineLoopState::Break));
+
+        let buffer = program.encode();
+        let view = ProgramView::new(&buffer).unwrap();
+        let mut machine = Machine::from_view(&view);
+        assert_eq!(machine.run(), Ok(MachineLoopState::Break));
+        assert_eq!(machine.stack(), owned.stack());
+        assert_eq!(machine.registers(), owned.registers());
+        assert_eq!(machine.snapshot(), owned.snapshot());
+    }
+
+    #[test]
+    fn view_machines_see_their_code() {
+        let program = countdown();
+        let buffer = program.encode();
+        let view = ProgramView::new(&buffer).unwrap();
+        let mut owned = Machine::new(&program);
+        let mut machine = Machine::from_view(&view);
+        for machine in [&mut owned, &mut machine] {
+            machine.add_watchpoint(Watchpoint::Register(Register1, WatchCondition::Write));
+            assert_eq!(
+                machine.run(),
+                Ok(MachineLoopState::Watchpoint { id: 0, pc: 1 })
+            );
+            machine.clear_watchpoints();
+            machine.set_conditional_breakpoint(12, |state| {
+                state.len() == 16
+                    && state
+                        .op(state.current)
+                        .is_some_and(|op| op.code == JumpIfZero)
+            });
+            assert_eq!(machine.run(), Ok(MachineLoopState::Breakpoint(12)));
+        }
+        assert_eq!(machine.snapshot(), owned.snapshot());
+    }
+
+    #[test]
+    fn views_reject_invalid_code_up_front() {
+        let mut buffer = countdown().encode_raw();
+        buffer[20] = 0xff;
+        let Err(FormatError::Decode(error)) = ProgramView::new(&buffer) else {
+            panic!("expected a decode error");
+        };
+        assert_eq!((error.offset, error.index), (20, 2));
+        assert_eq!(error.reason, DecodeReason::UnknownOpCode(0xff));
+    }
+}